@@ -0,0 +1,114 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use regex::Regex;
+
+use sleigh_preprocessor::internals::Directive;
+
+lazy_static::lazy_static! {
+    static ref INCLUDE_RE: Regex = Regex::new(r#"^\s*@include\s+"(.*)"\s*$"#).unwrap();
+    static ref DEFINE1_RE: Regex = Regex::new(r#"^\s*@define\s+([0-9A-Z_a-z]+)\s+"(.*)"\s*$"#).unwrap();
+    static ref DEFINE2_RE: Regex = Regex::new(r"^\s*@define\s+([0-9A-Z_a-z]+)\s+(\S+)\s*$").unwrap();
+    static ref DEFINE3_RE: Regex = Regex::new(r"^\s*@define\s+([0-9A-Z_a-z]+)\s*$").unwrap();
+    static ref UNDEF_RE: Regex = Regex::new(r"^\s*@undef\s+([0-9A-Z_a-z]+)\s*$").unwrap();
+    static ref IFDEF_RE: Regex = Regex::new(r"^\s*@ifdef\s+([0-9A-Z_a-z]+)\s*$").unwrap();
+    static ref IFNDEF_RE: Regex = Regex::new(r"^\s*@ifndef\s+([0-9A-Z_a-z]+)\s*$").unwrap();
+    static ref IF_RE: Regex = Regex::new(r"^\s*@if\s+(.*)").unwrap();
+    static ref ELIF_RE: Regex = Regex::new(r"^\s*@elif\s+(.*)").unwrap();
+    static ref ENDIF_RE: Regex = Regex::new(r"^\s*@endif\s*$").unwrap();
+    static ref ELSE_RE: Regex = Regex::new(r"^\s*@else\s*$").unwrap();
+    static ref FULL_LINE_COMMENT_RE: Regex = Regex::new(r"^\s*#.*").unwrap();
+}
+
+const LINES: &[&str] = &[
+    "# a comment line",
+    ":add rd, rs is op=1 & rd & rs { rd = rd + rs; }",
+    "@include \"arm.sinc\"",
+    "@define ENDIAN \"big\"",
+    "@define SIZE 4",
+    "@define THUMB",
+    "@undef THUMB",
+    "@ifdef THUMB",
+    "@ifndef VERSION_5",
+    "@if defined(VERSION_6) && ENDIAN == \"big\"",
+    "@elif defined(VERSION_5)",
+    "@else",
+    "@endif",
+    "define register offset=0 size=4 [ r0 r1 r2 r3 ];",
+];
+
+/// The way directives were recognized before the scanner: a regular expression per directive,
+/// tried in order, after replacing full-line comments.
+fn parse_with_regexes(line: &str) -> Option<Directive<'_>> {
+    let group = |re: &Regex, i| re.captures(line).map(|m| m.get(i).unwrap().as_str());
+    if let Some(path) = group(&INCLUDE_RE, 1) {
+        Some(Directive::Include(path))
+    } else if let Some(m) = DEFINE1_RE.captures(line) {
+        Some(Directive::Define {
+            name: m.get(1).unwrap().as_str(),
+            value: Some(m.get(2).unwrap().as_str()),
+            quoted: true,
+        })
+    } else if let Some(m) = DEFINE2_RE.captures(line) {
+        Some(Directive::Define {
+            name: m.get(1).unwrap().as_str(),
+            value: Some(m.get(2).unwrap().as_str()),
+            quoted: false,
+        })
+    } else if let Some(name) = group(&DEFINE3_RE, 1) {
+        Some(Directive::Define {
+            name,
+            value: None,
+            quoted: false,
+        })
+    } else if let Some(name) = group(&UNDEF_RE, 1) {
+        Some(Directive::Undef(name))
+    } else if let Some(name) = group(&IFDEF_RE, 1) {
+        Some(Directive::Ifdef(name))
+    } else if let Some(name) = group(&IFNDEF_RE, 1) {
+        Some(Directive::Ifndef(name))
+    } else if let Some(expression) = group(&IF_RE, 1) {
+        Some(Directive::If(expression))
+    } else if let Some(expression) = group(&ELIF_RE, 1) {
+        Some(Directive::Elif(expression))
+    } else if ENDIF_RE.is_match(line) {
+        Some(Directive::Endif)
+    } else if ELSE_RE.is_match(line) {
+        Some(Directive::Else)
+    } else {
+        None
+    }
+}
+
+fn directive_lines(c: &mut Criterion) {
+    for line in LINES {
+        assert_eq!(
+            Directive::parse(line),
+            parse_with_regexes(line),
+            "{:?}",
+            line
+        );
+    }
+    let mut group = c.benchmark_group("directive lines");
+    group.bench_function("regexes", |b| {
+        b.iter(|| {
+            for line in LINES {
+                let line = FULL_LINE_COMMENT_RE.replace(line, "").to_string();
+                if line.starts_with('@') {
+                    black_box(parse_with_regexes(&line));
+                }
+            }
+        })
+    });
+    group.bench_function("scanner", |b| {
+        b.iter(|| {
+            for line in LINES {
+                if !line.trim_start().starts_with('#') && line.starts_with('@') {
+                    black_box(Directive::parse(line));
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, directive_lines);
+criterion_main!(benches);
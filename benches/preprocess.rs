@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::fs;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn preprocess_file(c: &mut Criterion) {
+    let directory = std::env::temp_dir().join("sleigh_preprocessor_bench");
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("large.slaspec");
+    let mut source = String::new();
+    for i in 0..2000 {
+        source.push_str(&format!("@define NAME_{} \"{}\"\n", i, i));
+        source.push_str(&format!("@if NAME_{} == \"{}\"\n", i, i));
+        source.push_str(&format!(":insn_{} is op={} {{ }}\n", i, i));
+        source.push_str("@else\n# never\n@endif\n");
+    }
+    fs::write(&path, source).unwrap();
+    c.bench_function("preprocess large file", |b| {
+        b.iter(|| {
+            let mut writer = String::new();
+            let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &path, true);
+            preprocessor.process(&mut writer).unwrap();
+            black_box(writer)
+        })
+    });
+
+    // every include enters a file and returns from it on a large output
+    let path = directory.join("includes.slaspec");
+    fs::write(directory.join("small.sinc"), ":insn is op=0 { }\n").unwrap();
+    let mut source = String::new();
+    for i in 0..2000 {
+        source.push_str(&format!(":insn_{} is op={} {{ }}\n", i, i));
+        source.push_str("@include \"small.sinc\"\n");
+    }
+    fs::write(&path, source).unwrap();
+    c.bench_function("preprocess many includes", |b| {
+        b.iter(|| {
+            let mut writer = String::new();
+            let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &path, true);
+            preprocessor.process(&mut writer).unwrap();
+            black_box(writer)
+        })
+    });
+}
+
+criterion_group!(benches, preprocess_file);
+criterion_main!(benches);
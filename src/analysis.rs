@@ -0,0 +1,434 @@
+//! Static checks of the conditionals of a source.
+//!
+//! Conditions are turned into propositional formulas over atoms such as `defined(X)` or
+//! `X == "a"` and checked for satisfiability, so branches are judged for every possible set
+//! of definitions rather than for some concrete ones.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::boolean_expression::{BinaryOp, Expr, KnownDefinitions, UnaryOp};
+use crate::directive::{directive_text, Directive};
+use crate::errors::{Error, ErrorKind, PreprocessorError, Result};
+
+/// Formulas with more atoms are not checked, to bound the time spent on a condition.
+const MAX_ATOMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FindingKind {
+    /// No set of definitions takes the branch.
+    Unreachable,
+    /// The condition is the same as the one of an earlier branch of the conditional.
+    DuplicateCondition,
+    /// The condition holds whenever it is evaluated, so later branches are never taken.
+    AlwaysTrue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub line_no: usize,
+    pub line: String,
+    /// Line of the earlier branch with the same condition.
+    pub related_line_no: Option<usize>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FindingKind::Unreachable => write!(f, "branch is never taken")?,
+            FindingKind::DuplicateCondition => write!(
+                f,
+                "condition duplicates the one at line {}",
+                self.related_line_no.unwrap_or_default()
+            )?,
+            FindingKind::AlwaysTrue => write!(f, "condition is always true")?,
+        }
+        write!(f, ": {}", self.line)
+    }
+}
+
+/// Checks the conditionals of a single source, included files are not read.
+///
+/// Branches nested in a branch that is never taken are not reported. A definition changed
+/// by `@define` or `@undef` is considered unrelated to its previous value. Unbalanced
+/// conditionals are errors, as when specializing.
+pub fn check_conditionals<P: AsRef<Path>>(source: &str, path: P) -> Result<Vec<Finding>> {
+    let mut checker = Checker {
+        path: path.as_ref(),
+        atoms: Vec::new(),
+        versions: HashMap::new(),
+        stack: Vec::new(),
+        findings: Vec::new(),
+        line_no: 0,
+    };
+    for line in source.lines() {
+        checker.line_no += 1;
+        checker.line(line)?;
+    }
+    if !checker.stack.is_empty() {
+        return Err(checker.error(ErrorKind::UnbalancedConditional, "missing @endif", ""));
+    }
+    Ok(checker.findings)
+}
+
+/// Propositional formula of a condition.
+#[derive(Debug, Clone, PartialEq)]
+enum Formula {
+    Const(bool),
+    Atom(usize),
+    Not(Box<Formula>),
+    And(Box<Formula>, Box<Formula>),
+    Or(Box<Formula>, Box<Formula>),
+    Xor(Box<Formula>, Box<Formula>),
+}
+
+/// Condition whose value is not known, definitions are tagged with the number of times they
+/// were changed before.
+#[derive(Debug, Clone, PartialEq)]
+enum Atom {
+    Defined(String, usize),
+    /// `NAME == literal`, where the literal is a string or an integer.
+    Equals(String, usize, Expr),
+    Other(Expr, Vec<(String, usize)>),
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// Condition to reach the conditional.
+    path: Formula,
+    /// Conditions and lines of the branches seen so far.
+    branches: Vec<(Formula, usize)>,
+    /// Whether the conditional is reached and the current branch may be taken.
+    reachable: bool,
+    outer_reachable: bool,
+    saw_else: bool,
+}
+
+struct Checker<'a> {
+    path: &'a Path,
+    atoms: Vec<Atom>,
+    versions: HashMap<String, usize>,
+    stack: Vec<Frame>,
+    findings: Vec<Finding>,
+    line_no: usize,
+}
+
+impl<'a> Checker<'a> {
+    fn line(&mut self, line: &str) -> Result<()> {
+        let directive = match directive_text(line, true) {
+            Some(directive) => directive,
+            None => return Ok(()),
+        };
+        let parsed = Directive::parse(directive).ok_or_else(|| {
+            self.error(
+                ErrorKind::UnrecognizedDirective,
+                "unrecognized preprocessor directive",
+                line,
+            )
+        })?;
+        match parsed {
+            Directive::Include(_) => {}
+            Directive::Define { name, .. } | Directive::Undef(name) => {
+                *self.versions.entry(name.to_string()).or_default() += 1;
+            }
+            Directive::Ifdef(name) => {
+                self.enter_if();
+                let condition = Expr::Defined(name.to_string());
+                self.branch(line, Some(&condition));
+            }
+            Directive::Ifndef(name) => {
+                self.enter_if();
+                let defined = Expr::Defined(name.to_string());
+                self.branch(line, Some(&Expr::Unary(UnaryOp::Not, Box::new(defined))));
+            }
+            Directive::If(expression) => {
+                self.enter_if();
+                let condition = self.parse(expression, line)?;
+                self.branch(line, Some(&condition));
+            }
+            Directive::Elif(expression) => {
+                match self.stack.last() {
+                    None => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "elif outside of IF* directive",
+                            line,
+                        ))
+                    }
+                    Some(frame) if frame.saw_else => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "already saw else directive",
+                            line,
+                        ))
+                    }
+                    _ => {}
+                }
+                let condition = self.parse(expression, line)?;
+                self.branch(line, Some(&condition));
+            }
+            Directive::Else => {
+                match self.stack.last_mut() {
+                    None => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "else outside of IF* directive",
+                            line,
+                        ))
+                    }
+                    Some(frame) if frame.saw_else => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "duplicate else directive",
+                            line,
+                        ))
+                    }
+                    Some(frame) => frame.saw_else = true,
+                }
+                self.branch(line, None);
+            }
+            Directive::Endif => {
+                if self.stack.pop().is_none() {
+                    return Err(self.error(
+                        ErrorKind::UnbalancedConditional,
+                        "not in IF* directive",
+                        line,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn enter_if(&mut self) {
+        let (path, outer_reachable) = match self.stack.last() {
+            Some(frame) => (frame.current(), frame.reachable),
+            None => (Formula::Const(true), true),
+        };
+        self.stack.push(Frame {
+            path,
+            branches: Vec::new(),
+            reachable: false,
+            outer_reachable,
+            saw_else: false,
+        });
+    }
+
+    /// Checks the next branch of the innermost conditional, `None` for `@else`.
+    fn branch(&mut self, line: &str, condition: Option<&Expr>) {
+        let formula = match condition {
+            Some(condition) => self.formula(&condition.simplify(&KnownDefinitions::new())),
+            None => Formula::Const(true),
+        };
+        let line_no = self.line_no;
+        let frame = self.stack.last_mut().unwrap();
+        let reached = frame.reached();
+        frame.branches.push((formula.clone(), line_no));
+        frame.reachable = false;
+        if !frame.outer_reachable {
+            return;
+        }
+        let duplicate = frame.branches[..frame.branches.len() - 1]
+            .iter()
+            .find(|(earlier, _)| condition.is_some() && *earlier == formula)
+            .map(|(_, line_no)| *line_no);
+        let finding = if let Some(related) = duplicate {
+            Some((FindingKind::DuplicateCondition, Some(related)))
+        } else if !satisfiable(&and(reached.clone(), formula.clone()), &self.atoms) {
+            Some((FindingKind::Unreachable, None))
+        } else {
+            frame.reachable = true;
+            let always_true =
+                condition.is_some() && !satisfiable(&and(reached, not(formula)), &self.atoms);
+            if always_true {
+                Some((FindingKind::AlwaysTrue, None))
+            } else {
+                None
+            }
+        };
+        if let Some((kind, related_line_no)) = finding {
+            self.findings.push(Finding {
+                kind,
+                line_no,
+                line: line.to_string(),
+                related_line_no,
+            });
+        }
+    }
+
+    fn formula(&mut self, expr: &Expr) -> Formula {
+        if let Some(value) = expr.as_condition() {
+            return Formula::Const(value);
+        }
+        match expr {
+            Expr::Unary(UnaryOp::Not, expr) => not(self.formula(expr)),
+            Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or | BinaryOp::Xor), l, r) => {
+                let (l, r) = (Box::new(self.formula(l)), Box::new(self.formula(r)));
+                match op {
+                    BinaryOp::And => Formula::And(l, r),
+                    BinaryOp::Or => Formula::Or(l, r),
+                    _ => Formula::Xor(l, r),
+                }
+            }
+            Expr::Defined(name) => self.atom(Atom::Defined(name.clone(), self.version(name))),
+            Expr::Binary(op @ (BinaryOp::Eq | BinaryOp::Ne), l, r) => {
+                let equals = match (&**l, &**r) {
+                    (Expr::Identifier(name), literal @ (Expr::String(_) | Expr::Integer(_)))
+                    | (literal @ (Expr::String(_) | Expr::Integer(_)), Expr::Identifier(name)) => {
+                        Atom::Equals(name.clone(), self.version(name), literal.clone())
+                    }
+                    _ => self.other(expr),
+                };
+                match (op, equals) {
+                    (BinaryOp::Ne, equals @ Atom::Equals(..)) => not(self.atom(equals)),
+                    (_, atom) => self.atom(atom),
+                }
+            }
+            _ => {
+                let atom = self.other(expr);
+                self.atom(atom)
+            }
+        }
+    }
+
+    fn other(&self, expr: &Expr) -> Atom {
+        let versions = expr
+            .referenced_identifiers()
+            .into_iter()
+            .map(|name| (name.to_string(), self.version(name)))
+            .collect();
+        Atom::Other(expr.clone(), versions)
+    }
+
+    fn atom(&mut self, atom: Atom) -> Formula {
+        match self.atoms.iter().position(|known| *known == atom) {
+            Some(i) => Formula::Atom(i),
+            None => {
+                self.atoms.push(atom);
+                Formula::Atom(self.atoms.len() - 1)
+            }
+        }
+    }
+
+    fn version(&self, name: &str) -> usize {
+        self.versions.get(name).copied().unwrap_or_default()
+    }
+
+    fn parse(&self, expression: &str, line: &str) -> Result<Expr> {
+        Expr::parse(expression)
+            .map_err(|e| self.error(e.kind(), &format!("parser error: {}", e), line))
+    }
+
+    fn error(&self, kind: ErrorKind, message: &str, line: &str) -> Error {
+        // included files are not read, so lines are numbered as in the source
+        PreprocessorError::in_file(kind, message, self.path, self.line_no, self.line_no, line)
+            .into()
+    }
+}
+
+impl Frame {
+    /// Condition to reach the next branch: no earlier branch is taken.
+    fn reached(&self) -> Formula {
+        self.branches
+            .iter()
+            .fold(self.path.clone(), |reached, (condition, _)| {
+                and(reached, not(condition.clone()))
+            })
+    }
+
+    /// Condition to reach the lines of the current branch.
+    fn current(&self) -> Formula {
+        let (condition, earlier) = self.branches.split_last().unwrap();
+        let reached = earlier
+            .iter()
+            .fold(self.path.clone(), |reached, (condition, _)| {
+                and(reached, not(condition.clone()))
+            });
+        and(reached, condition.0.clone())
+    }
+}
+
+fn and(l: Formula, r: Formula) -> Formula {
+    match (l, r) {
+        (Formula::Const(true), f) | (f, Formula::Const(true)) => f,
+        (l, r) => Formula::And(Box::new(l), Box::new(r)),
+    }
+}
+
+fn not(f: Formula) -> Formula {
+    match f {
+        Formula::Const(b) => Formula::Const(!b),
+        Formula::Not(f) => *f,
+        f => Formula::Not(Box::new(f)),
+    }
+}
+
+/// Whether some assignment of the atoms of `formula` consistent with their meaning makes it
+/// true. Formulas with too many atoms are assumed satisfiable.
+fn satisfiable(formula: &Formula, atoms: &[Atom]) -> bool {
+    let mut used = Vec::new();
+    formula.collect_atoms(&mut used);
+    used.sort_unstable();
+    used.dedup();
+    if used.len() > MAX_ATOMS {
+        return true;
+    }
+    let used: Vec<(usize, &Atom)> = used.into_iter().map(|i| (i, &atoms[i])).collect();
+    (0..1u32 << used.len()).any(|bits| {
+        let value = |atom: usize| {
+            let position = used.iter().position(|(i, _)| *i == atom).unwrap();
+            bits & (1 << position) != 0
+        };
+        consistent(&used, &value) && formula.eval(&value)
+    })
+}
+
+/// Checks an assignment against the meaning of the atoms: a name equals at most one value and
+/// is defined if it equals any.
+fn consistent(used: &[(usize, &Atom)], value: &dyn Fn(usize) -> bool) -> bool {
+    used.iter().all(|&(i, atom)| match atom {
+        Atom::Equals(name, version, literal) if value(i) => {
+            used.iter().all(|&(j, other)| match other {
+                Atom::Equals(n, v, l) if n == name && v == version => {
+                    !value(j) || l == literal || !same_kind(l, literal)
+                }
+                Atom::Defined(n, v) if n == name && v == version => value(j),
+                _ => true,
+            })
+        }
+        _ => true,
+    })
+}
+
+/// Strings are compared with strings and integers with integers, so different literals of
+/// different kinds may both equal a definition, e.g. `"01"` and `1`.
+fn same_kind(l: &Expr, r: &Expr) -> bool {
+    std::mem::discriminant(l) == std::mem::discriminant(r)
+}
+
+impl Formula {
+    fn collect_atoms(&self, atoms: &mut Vec<usize>) {
+        match self {
+            Self::Const(_) => {}
+            Self::Atom(i) => atoms.push(*i),
+            Self::Not(f) => f.collect_atoms(atoms),
+            Self::And(l, r) | Self::Or(l, r) | Self::Xor(l, r) => {
+                l.collect_atoms(atoms);
+                r.collect_atoms(atoms);
+            }
+        }
+    }
+
+    fn eval(&self, value: &dyn Fn(usize) -> bool) -> bool {
+        match self {
+            Self::Const(b) => *b,
+            Self::Atom(i) => value(*i),
+            Self::Not(f) => !f.eval(value),
+            Self::And(l, r) => l.eval(value) && r.eval(value),
+            Self::Or(l, r) => l.eval(value) || r.eval(value),
+            Self::Xor(l, r) => l.eval(value) ^ r.eval(value),
+        }
+    }
+}
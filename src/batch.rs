@@ -0,0 +1,140 @@
+//! Preprocessing of many root files at once.
+//!
+//! Roots are spread over a pool of threads which read included files through one
+//! [`IncludeCache`], so that files included by several roots are only read once.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cache::IncludeCache;
+use crate::diagnostic::Diagnostic;
+use crate::errors::Result;
+use crate::location::Location;
+use crate::{Definitions, SleighPreprocessor};
+
+/// File to preprocess with the definitions it starts with.
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub path: PathBuf,
+    pub definitions: Definitions,
+}
+
+impl Root {
+    pub fn new(path: impl Into<PathBuf>, definitions: Definitions) -> Self {
+        Self {
+            path: path.into(),
+            definitions,
+        }
+    }
+}
+
+/// Result of preprocessing a root.
+#[derive(Debug)]
+pub struct RootResult {
+    pub path: PathBuf,
+    pub output: Result<RootOutput>,
+    /// Warnings found before preprocessing stopped, even if it failed.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Time spent preprocessing the root, including waiting for files other threads read.
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub struct RootOutput {
+    pub text: String,
+    pub definitions: Definitions,
+    pub locations: Vec<Location>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchPreprocessor {
+    compatible: bool,
+    indented_directives: bool,
+    include_dirs: Vec<PathBuf>,
+    threads: usize,
+    cache: IncludeCache,
+}
+
+impl BatchPreprocessor {
+    pub fn new(is_compatible: bool) -> Self {
+        Self {
+            compatible: is_compatible,
+            indented_directives: true,
+            include_dirs: Vec::new(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            cache: IncludeCache::new(),
+        }
+    }
+
+    /// See [`SleighPreprocessor::with_indented_directives`].
+    pub fn with_indented_directives(mut self, indented_directives: bool) -> Self {
+        self.indented_directives = indented_directives;
+        self
+    }
+
+    /// See [`SleighPreprocessor::with_include_dir`].
+    pub fn with_include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Number of threads, the available parallelism by default.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Cache to read files through, a new one by default. Passing the cache of a previous
+    /// batch reuses what it read.
+    pub fn with_include_cache(mut self, cache: IncludeCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Preprocesses every root, the results are in the order of `roots`.
+    pub fn process(&self, roots: &[Root]) -> Vec<RootResult> {
+        let next = AtomicUsize::new(0);
+        let results: Vec<_> = roots.iter().map(|_| Mutex::new(None)).collect();
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(roots.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    match roots.get(i) {
+                        Some(root) => *results[i].lock().unwrap() = Some(self.process_root(root)),
+                        None => break,
+                    }
+                });
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| result.into_inner().unwrap().unwrap())
+            .collect()
+    }
+
+    fn process_root(&self, root: &Root) -> RootResult {
+        let start = Instant::now();
+        let mut preprocessor =
+            SleighPreprocessor::new(root.definitions.clone(), &root.path, self.compatible)
+                .with_indented_directives(self.indented_directives)
+                .with_include_cache(self.cache.clone());
+        for dir in &self.include_dirs {
+            preprocessor = preprocessor.with_include_dir(dir);
+        }
+        let mut text = String::new();
+        let output = preprocessor.process(&mut text).map(|()| RootOutput {
+            text,
+            definitions: preprocessor.take_definitions(),
+            locations: preprocessor.take_locations(),
+        });
+        RootResult {
+            path: root.path.clone(),
+            output,
+            diagnostics: preprocessor.diagnostics().to_vec(),
+            elapsed: start.elapsed(),
+        }
+    }
+}
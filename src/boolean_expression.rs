@@ -1,12 +1,24 @@
 use std::collections::HashMap;
-use std::ops;
+use std::fmt;
+use std::path::Path;
 
-use pest::iterators::Pair;
-use pest::prec_climber::{Assoc, Operator, PrecClimber};
-use pest::Parser;
+use pest::error::ErrorVariant;
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use pest::{Parser, Span};
 use pest_derive::Parser;
+use regex::Regex;
 
 use super::errors::{Error, Result};
+use super::escape::unescape;
+
+mod ast;
+pub mod functions;
+mod simplify;
+
+pub use ast::{BinaryOp, Expr, Pattern, UnaryOp};
+pub use functions::{Function, Functions};
+pub use simplify::KnownDefinitions;
 
 #[derive(Parser)]
 #[grammar = "boolean_expression.pest"]
@@ -14,101 +26,315 @@ pub struct BooleanExpressionParser;
 
 type Definitions = HashMap<String, String>;
 
+lazy_static::lazy_static! {
+    static ref BUILTIN_FUNCTIONS: Functions = Functions::default();
+    static ref PRATT_PARSER: PrattParser<Rule> = PrattParser::new()
+        .op(Op::infix(Rule::OR_OP, Assoc::Left))
+        .op(Op::infix(Rule::XOR_OP, Assoc::Left))
+        .op(Op::infix(Rule::AND_OP, Assoc::Left))
+        .op(Op::infix(Rule::BIT_OR_OP, Assoc::Left))
+        .op(Op::infix(Rule::BIT_AND_OP, Assoc::Left))
+        .op(Op::infix(Rule::EQ_OP, Assoc::Left)
+            | Op::infix(Rule::NE_OP, Assoc::Left)
+            | Op::postfix(Rule::expr_match))
+        .op(Op::infix(Rule::LT_OP, Assoc::Left)
+            | Op::infix(Rule::LE_OP, Assoc::Left)
+            | Op::infix(Rule::GT_OP, Assoc::Left)
+            | Op::infix(Rule::GE_OP, Assoc::Left))
+        .op(Op::infix(Rule::SHL_OP, Assoc::Left) | Op::infix(Rule::SHR_OP, Assoc::Left))
+        .op(Op::infix(Rule::ADD_OP, Assoc::Left) | Op::infix(Rule::SUB_OP, Assoc::Left))
+        .op(Op::infix(Rule::MUL_OP, Assoc::Left)
+            | Op::infix(Rule::DIV_OP, Assoc::Left)
+            | Op::infix(Rule::REM_OP, Assoc::Left))
+        .op(Op::prefix(Rule::NOT_OP) | Op::prefix(Rule::NEG_OP) | Op::prefix(Rule::BIT_NOT_OP));
+}
+
+/// Value of an expression or of one of its operands.
+///
+/// Definitions are always strings; they are interpreted as integers only when an operator
+/// requires it (e.g. `FEATURES & 0x4`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl Value {
+    /// Interprets the value as a condition: integers are true when non-zero, strings are not
+    /// allowed.
+    pub fn as_bool(&self) -> Result<bool> {
+        match self {
+            Self::Bool(b) => Ok(*b),
+            Self::Integer(i) => Ok(*i != 0),
+            Self::String(s) => Err(Error::Type(format!(
+                "expected a condition, found string \"{}\"",
+                s
+            ))),
+        }
+    }
+
+    /// Interprets the value as an integer: booleans are `0` or `1`, strings must contain a
+    /// decimal or `0x`-prefixed hexadecimal number.
+    pub fn as_integer(&self) -> Result<i64> {
+        match self {
+            Self::Bool(b) => Ok(*b as i64),
+            Self::Integer(i) => Ok(*i),
+            Self::String(s) => {
+                parse_integer(s).ok_or_else(|| Error::Type(format!("\"{}\" is not an integer", s)))
+            }
+        }
+    }
+
+    /// Interprets the value as a string: integers are formatted in decimal, booleans are not
+    /// allowed.
+    pub fn as_string(&self) -> Result<String> {
+        match self {
+            Self::Bool(b) => Err(Error::Type(format!("expected a string, found {}", b))),
+            Self::Integer(i) => Ok(i.to_string()),
+            Self::String(s) => Ok(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Integer(i) => write!(f, "{}", i),
+            Self::String(s) => write!(f, "\"{}\"", s),
+        }
+    }
+}
+
+fn parse_integer(input: &str) -> Option<i64> {
+    let input = input.trim();
+    let (negative, digits) = match input.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, input),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    if negative {
+        value.checked_neg()
+    } else {
+        Some(value)
+    }
+}
+
+/// Everything an expression can refer to during evaluation.
+pub struct Context<'a> {
+    definitions: &'a Definitions,
+    functions: &'a Functions,
+    directory: Option<&'a Path>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(definitions: &'a Definitions, functions: &'a Functions) -> Self {
+        Self {
+            definitions,
+            functions,
+            directory: None,
+        }
+    }
+
+    /// Sets the directory relative paths are resolved against, usually the directory of the
+    /// file containing the expression.
+    pub fn with_directory(mut self, directory: Option<&'a Path>) -> Self {
+        self.directory = directory;
+        self
+    }
+
+    pub fn definitions(&self) -> &Definitions {
+        self.definitions
+    }
+
+    pub fn functions(&self) -> &Functions {
+        self.functions
+    }
+
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory
+    }
+}
+
 pub fn parse_boolean_expression(input: &str, definitions: &Definitions) -> Result<bool> {
+    Expr::parse(input)?.eval(definitions)
+}
+
+pub fn evaluate_boolean_expression(input: &str, context: &Context) -> Result<bool> {
+    Expr::parse(input)?.eval_with(context)
+}
+
+fn parse_expression(input: &str) -> Result<Expr> {
     let mut expr = BooleanExpressionParser::parse(Rule::boolean_expression, input)
         .map_err(|e| Error::Parsing(format!("{}", e)))?;
-    parse_expr(expr.next().unwrap(), definitions)
+    parse_expr(expr.next().unwrap())
 }
 
-fn parse_expr(pair: Pair<Rule>, definitions: &Definitions) -> Result<bool> {
+fn parse_expr(pair: Pair<Rule>) -> Result<Expr> {
     debug_assert_eq!(pair.as_rule(), Rule::expr);
-    let climber = PrecClimber::new(vec![
-        Operator::new(Rule::OR_OP, Assoc::Left),
-        Operator::new(Rule::XOR_OP, Assoc::Left),
-        Operator::new(Rule::AND_OP, Assoc::Left),
-    ]);
-    consume(pair, &climber, definitions)
-}
-
-fn consume(
-    pair: Pair<Rule>,
-    climber: &PrecClimber<Rule>,
-    definitions: &Definitions,
-) -> Result<bool> {
-    let primary = |pair| consume(pair, climber, definitions);
-    let infix = |l: Result<bool>, op: Pair<Rule>, r: Result<bool>| {
-        let l = l?;
-        let r = r?;
-        let result = match op.as_rule() {
-            Rule::OR_OP => l || r,
-            Rule::XOR_OP => l ^ r,
-            Rule::AND_OP => l && r,
-            _ => unreachable!(op),
-        };
-        Ok(result)
-    };
+    consume(pair.into_inner())
+}
 
-    match pair.as_rule() {
-        Rule::expr => climber.climb(pair.into_inner(), primary, infix),
-        Rule::boolean_clause => parse_boolean_clause(pair, definitions),
-        _ => unreachable!(pair),
-    }
+fn consume(pairs: Pairs<Rule>) -> Result<Expr> {
+    check_match_operands(pairs.clone())?;
+    PRATT_PARSER
+        .map_primary(|pair| match pair.as_rule() {
+            Rule::expr_paren => parse_expr_paren(pair),
+            Rule::expr_defined => parse_expr_defined(pair),
+            Rule::expr_call => parse_expr_call(pair),
+            Rule::expr_term => parse_expr_term(pair),
+            _ => unreachable!("{}", pair),
+        })
+        .map_prefix(|op, r| {
+            let op = match op.as_rule() {
+                Rule::NOT_OP => UnaryOp::Not,
+                Rule::NEG_OP => UnaryOp::Neg,
+                Rule::BIT_NOT_OP => UnaryOp::BitNot,
+                _ => unreachable!("{}", op),
+            };
+            // a negated literal is a negative literal, which is how one is printed
+            match (op, r?) {
+                (UnaryOp::Neg, Expr::Integer(i)) if i.checked_neg().is_some() => {
+                    Ok(Expr::Integer(-i))
+                }
+                (op, r) => Ok(Expr::Unary(op, Box::new(r))),
+            }
+        })
+        .map_postfix(|l, op| parse_expr_match(l?, op))
+        .map_infix(|l, op, r| {
+            let op = match op.as_rule() {
+                Rule::OR_OP => BinaryOp::Or,
+                Rule::XOR_OP => BinaryOp::Xor,
+                Rule::AND_OP => BinaryOp::And,
+                Rule::BIT_OR_OP => BinaryOp::BitOr,
+                Rule::BIT_AND_OP => BinaryOp::BitAnd,
+                Rule::EQ_OP => BinaryOp::Eq,
+                Rule::NE_OP => BinaryOp::Ne,
+                Rule::LT_OP => BinaryOp::Lt,
+                Rule::LE_OP => BinaryOp::Le,
+                Rule::GT_OP => BinaryOp::Gt,
+                Rule::GE_OP => BinaryOp::Ge,
+                Rule::SHL_OP => BinaryOp::Shl,
+                Rule::SHR_OP => BinaryOp::Shr,
+                Rule::ADD_OP => BinaryOp::Add,
+                Rule::SUB_OP => BinaryOp::Sub,
+                Rule::MUL_OP => BinaryOp::Mul,
+                Rule::DIV_OP => BinaryOp::Div,
+                Rule::REM_OP => BinaryOp::Rem,
+                _ => unreachable!("{}", op),
+            };
+            Ok(Expr::Binary(op, Box::new(l?), Box::new(r?)))
+        })
+        .parse(pairs)
 }
 
-fn parse_boolean_clause(pair: Pair<Rule>, definitions: &Definitions) -> Result<bool> {
-    debug_assert_eq!(pair.as_rule(), Rule::boolean_clause);
-    let pair = pair.into_inner().next().unwrap();
-    match pair.as_rule() {
-        Rule::expr_not => parse_expr_not(pair, definitions),
-        Rule::expr_paren => parse_expr_paren(pair, definitions),
-        Rule::expr_eq => parse_expr_eq(pair, definitions),
-        Rule::expr_defined => parse_expr_defined(pair, definitions),
-        _ => unreachable!(pair),
+fn parse_expr_match(l: Expr, pair: Pair<Rule>) -> Result<Expr> {
+    debug_assert_eq!(pair.as_rule(), Rule::expr_match);
+    let mut pairs = pair.into_inner();
+    let negated = pairs.next().unwrap().as_rule() == Rule::NOT_MATCH_OP;
+    let pattern = pairs.next().unwrap().into_inner().next().unwrap();
+    let regex = Regex::new(&pattern.as_str().replace("\\/", "/")).map_err(|e| {
+        parsing_error(
+            format!("invalid regular expression: {}", e),
+            pattern.as_span(),
+        )
+    })?;
+    Ok(Expr::Match {
+        expr: Box::new(l),
+        pattern: Pattern::new(regex),
+        negated,
+    })
+}
+
+/// Rejects a match of an operand starting with a unary operator, e.g. `!X =~ /re/`.
+///
+/// The unary operator binds tighter, so the match would apply to `!X` rather than negate the
+/// match, which is rarely what was meant; parentheses make either reading explicit.
+fn check_match_operands(pairs: Pairs<Rule>) -> Result<()> {
+    let mut operand: Option<(Pair<Rule>, Pair<Rule>)> = None;
+    let mut prefix = None;
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::NOT_OP | Rule::NEG_OP | Rule::BIT_NOT_OP => {
+                prefix = prefix.or(Some(pair));
+            }
+            Rule::expr_match => {
+                if let Some((prefix, primary)) = operand.take() {
+                    let unary = prefix
+                        .as_span()
+                        .start_pos()
+                        .span(&primary.as_span().start_pos());
+                    let (unary, operand) = (unary.as_str().trim(), primary.as_str());
+                    let mut inner = pair.clone().into_inner();
+                    let op = inner.next().unwrap().as_str();
+                    let m = format!("{} {}", op, inner.next().unwrap().as_str());
+                    let message = format!(
+                        "`{}` applies before `{}`, write `{}({} {})` or `({}{}) {}`",
+                        unary, op, unary, operand, m, unary, operand, m
+                    );
+                    let span = prefix.as_span().start_pos().span(&pair.as_span().end_pos());
+                    return Err(parsing_error(message, span));
+                }
+            }
+            Rule::expr_paren | Rule::expr_defined | Rule::expr_call | Rule::expr_term => {
+                operand = prefix.take().map(|prefix| (prefix, pair));
+            }
+            _ => operand = None,
+        }
     }
+    Ok(())
 }
 
-fn parse_expr_not(pair: Pair<Rule>, definitions: &Definitions) -> Result<bool> {
-    debug_assert_eq!(pair.as_rule(), Rule::expr_not);
-    parse_expr_paren(pair.into_inner().next().unwrap(), definitions).map(ops::Not::not)
+/// Reports an error found after parsing at the position of `span` in the expression.
+fn parsing_error(message: String, span: Span) -> Error {
+    let error =
+        pest::error::Error::<Rule>::new_from_span(ErrorVariant::CustomError { message }, span);
+    Error::Parsing(format!("{}", error))
 }
 
-fn parse_expr_paren(pair: Pair<Rule>, definitions: &Definitions) -> Result<bool> {
+fn parse_expr_paren(pair: Pair<Rule>) -> Result<Expr> {
     debug_assert_eq!(pair.as_rule(), Rule::expr_paren);
-    parse_expr(pair.into_inner().next().unwrap(), definitions)
+    parse_expr(pair.into_inner().next().unwrap())
 }
 
-fn parse_expr_eq(pair: Pair<Rule>, definitions: &Definitions) -> Result<bool> {
-    debug_assert_eq!(pair.as_rule(), Rule::expr_eq);
+fn parse_expr_defined(pair: Pair<Rule>) -> Result<Expr> {
+    debug_assert_eq!(pair.as_rule(), Rule::expr_defined);
+    let pair = pair.into_inner();
+    Ok(Expr::Defined(pair.as_str().to_string()))
+}
+
+fn parse_expr_call(pair: Pair<Rule>) -> Result<Expr> {
+    debug_assert_eq!(pair.as_rule(), Rule::expr_call);
     let mut pairs = pair.into_inner();
-    let l = parse_expr_term(pairs.next().unwrap(), definitions)?;
-    let comp_op = pairs.next().unwrap();
-    let r = parse_expr_term(pairs.next().unwrap(), definitions)?;
-    let result = match comp_op.as_str() {
-        "!=" => l != r,
-        "==" => l == r,
-        _ => unreachable!(comp_op),
-    };
-    Ok(result)
+    let name = pairs.next().unwrap().as_str().to_string();
+    let args = pairs.map(parse_expr).collect::<Result<Vec<_>>>()?;
+    Ok(Expr::Call(name, args))
 }
 
-fn parse_expr_defined(pair: Pair<Rule>, definitions: &Definitions) -> Result<bool> {
-    debug_assert_eq!(pair.as_rule(), Rule::expr_defined);
-    let pair = pair.into_inner();
-    Ok(definitions.contains_key(pair.as_str()))
+fn parse_string(pair: Pair<Rule>) -> Result<String> {
+    debug_assert_eq!(pair.as_rule(), Rule::STRING);
+    unescape(pair.as_str()).map_err(|e| {
+        let span = pair.as_span().get(e.start()..e.end()).unwrap();
+        parsing_error(format!("{}", e), span)
+    })
 }
 
-fn parse_expr_term(pair: Pair<Rule>, definitions: &Definitions) -> Result<String> {
+fn parse_expr_term(pair: Pair<Rule>) -> Result<Expr> {
     debug_assert_eq!(pair.as_rule(), Rule::expr_term);
     let pair = pair.into_inner().next().unwrap();
     match pair.as_rule() {
-        Rule::IDENTIFIER => {
-            let key = pair.as_str();
-            Ok(definitions
-                .get(key)
-                .cloned()
-                .ok_or_else(|| Error::NotDefined(key.to_string()))?)
-        }
-        Rule::STRING => Ok(pair.as_str().to_string()),
-        _ => unreachable!(pair),
+        Rule::INTEGER => parse_integer(pair.as_str())
+            .map(Expr::Integer)
+            .ok_or_else(|| parsing_error("integer is too large".to_string(), pair.as_span())),
+        Rule::IDENTIFIER => Ok(Expr::Identifier(pair.as_str().to_string())),
+        Rule::QSTRING => parse_string(pair.into_inner().next().unwrap()).map(Expr::String),
+        _ => unreachable!("{}", pair),
     }
 }
@@ -0,0 +1,379 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use regex::Regex;
+
+use super::{Context, Definitions, Value, BUILTIN_FUNCTIONS};
+use crate::errors::{Error, Result};
+use crate::escape::escape;
+
+/// Parsed `@if`/`@elif` expression.
+///
+/// Parsing once and evaluating many times is cheaper than [`super::parse_boolean_expression`]
+/// when the same condition has to be checked against several sets of definitions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    /// Only produced by simplification, printed as `1` or `0`.
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Identifier(String),
+    /// `defined(NAME)`
+    Defined(String),
+    /// `name(args...)`
+    Call(String, Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `expr =~ /pattern/` or, if negated, `expr !~ /pattern/`
+    ///
+    /// The operators bind like `==` and `!=`, more loosely than the unary operators. A unary
+    /// operand must be parenthesized: `!X =~ /re/` does not parse, it is written `(!X) =~ /re/`
+    /// or, to negate the match, `!(X =~ /re/)` or `X !~ /re/`.
+    Match {
+        expr: Box<Expr>,
+        pattern: Pattern,
+        negated: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Or,
+    Xor,
+    And,
+    BitOr,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Compiled regular expression of a match operator, compared by its source text.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self> {
+        super::parse_expression(input)
+    }
+
+    /// Evaluates the expression as a condition using the built-in functions.
+    pub fn eval(&self, definitions: &Definitions) -> Result<bool> {
+        self.eval_with(&Context::new(definitions, &BUILTIN_FUNCTIONS))
+    }
+
+    /// Evaluates the expression as a condition.
+    pub fn eval_with(&self, context: &Context) -> Result<bool> {
+        self.evaluate(context)?.as_bool()
+    }
+
+    /// Computes the value of the expression.
+    ///
+    /// Like the preprocessor, all operands are evaluated, so `defined(X) && X == "1"` fails
+    /// when `X` is not defined.
+    pub fn evaluate(&self, context: &Context) -> Result<Value> {
+        match self {
+            Self::Bool(b) => Ok(Value::Bool(*b)),
+            Self::Integer(i) => Ok(Value::Integer(*i)),
+            Self::String(s) => Ok(Value::String(s.clone())),
+            Self::Identifier(name) => context
+                .definitions()
+                .get(name)
+                .cloned()
+                .map(Value::String)
+                .ok_or_else(|| Error::NotDefined(name.clone())),
+            Self::Defined(name) => Ok(Value::Bool(context.definitions().contains_key(name))),
+            Self::Call(name, args) => {
+                let function = context
+                    .functions()
+                    .get(name)
+                    .ok_or_else(|| Error::UnknownFunction(name.clone()))?;
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(context))
+                    .collect::<Result<Vec<_>>>()?;
+                function(&args, context)
+            }
+            Self::Unary(op, expr) => op.apply(expr.evaluate(context)?),
+            Self::Binary(op, l, r) => op.apply(l.evaluate(context)?, r.evaluate(context)?),
+            Self::Match {
+                expr,
+                pattern,
+                negated,
+            } => {
+                let subject = expr.evaluate(context)?.as_string()?;
+                Ok(Value::Bool(pattern.0.is_match(&subject) != *negated))
+            }
+        }
+    }
+
+    /// Names of all definitions the expression looks at, including those only checked with
+    /// `defined()`.
+    pub fn referenced_identifiers(&self) -> BTreeSet<&str> {
+        let mut identifiers = BTreeSet::new();
+        self.collect_identifiers(&mut identifiers);
+        identifiers
+    }
+
+    fn collect_identifiers<'a>(&'a self, identifiers: &mut BTreeSet<&'a str>) {
+        match self {
+            Self::Bool(_) | Self::Integer(_) | Self::String(_) => {}
+            Self::Identifier(name) | Self::Defined(name) => {
+                identifiers.insert(name);
+            }
+            Self::Call(_, args) => args
+                .iter()
+                .for_each(|arg| arg.collect_identifiers(identifiers)),
+            Self::Unary(_, expr) | Self::Match { expr, .. } => {
+                expr.collect_identifiers(identifiers)
+            }
+            Self::Binary(_, l, r) => {
+                l.collect_identifiers(identifiers);
+                r.collect_identifiers(identifiers);
+            }
+        }
+    }
+
+    /// Binding strength of the expression's outermost operator, used to decide where the
+    /// pretty-printer needs parentheses.
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Binary(op, _, _) => op.precedence(),
+            Self::Match { .. } => BinaryOp::Eq.precedence(),
+            Self::Unary(_, _) => UNARY_PRECEDENCE,
+            Self::Integer(i) if *i < 0 => UNARY_PRECEDENCE,
+            _ => PRIMARY_PRECEDENCE,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+const UNARY_PRECEDENCE: u8 = 11;
+const PRIMARY_PRECEDENCE: u8 = 12;
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Self::parse(input)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(b) => write!(f, "{}", *b as i64),
+            Self::Integer(i) => write!(f, "{}", i),
+            Self::String(s) => write!(f, "\"{}\"", escape(s)),
+            Self::Identifier(name) => write!(f, "{}", name),
+            Self::Defined(name) => write!(f, "defined({})", name),
+            Self::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Self::Unary(op, expr) => {
+                write!(f, "{}", op)?;
+                expr.fmt_operand(f, UNARY_PRECEDENCE)
+            }
+            // all binary operators are left-associative
+            Self::Binary(op, l, r) => {
+                l.fmt_operand(f, op.precedence())?;
+                write!(f, " {} ", op)?;
+                r.fmt_operand(f, op.precedence() + 1)
+            }
+            Self::Match {
+                expr,
+                pattern,
+                negated,
+            } => {
+                // a unary operand is parenthesized, see `Expr::Match`
+                let min_precedence = match expr.precedence() {
+                    UNARY_PRECEDENCE => PRIMARY_PRECEDENCE,
+                    _ => self.precedence(),
+                };
+                expr.fmt_operand(f, min_precedence)?;
+                write!(f, " {} {}", if *negated { "!~" } else { "=~" }, pattern)
+            }
+        }
+    }
+}
+
+impl UnaryOp {
+    fn apply(self, value: Value) -> Result<Value> {
+        match self {
+            Self::Not => Ok(Value::Bool(!value.as_bool()?)),
+            Self::Neg => value
+                .as_integer()?
+                .checked_neg()
+                .map(Value::Integer)
+                .ok_or_else(|| Error::Arithmetic(format!("integer overflow in `{}`", self))),
+            Self::BitNot => Ok(Value::Integer(!value.as_integer()?)),
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Not => "!",
+            Self::Neg => "-",
+            Self::BitNot => "~",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::Xor => 2,
+            Self::And => 3,
+            Self::BitOr => 4,
+            Self::BitAnd => 5,
+            Self::Eq | Self::Ne => 6,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => 7,
+            Self::Shl | Self::Shr => 8,
+            Self::Add | Self::Sub => 9,
+            Self::Mul | Self::Div | Self::Rem => 10,
+        }
+    }
+
+    fn apply(self, l: Value, r: Value) -> Result<Value> {
+        let result = match self {
+            Self::Or => Value::Bool(l.as_bool()? || r.as_bool()?),
+            Self::Xor => Value::Bool(l.as_bool()? ^ r.as_bool()?),
+            Self::And => Value::Bool(l.as_bool()? && r.as_bool()?),
+            Self::Eq => Value::Bool(equals(&l, &r)?),
+            Self::Ne => Value::Bool(!equals(&l, &r)?),
+            Self::Lt => Value::Bool(l.as_integer()? < r.as_integer()?),
+            Self::Le => Value::Bool(l.as_integer()? <= r.as_integer()?),
+            Self::Gt => Value::Bool(l.as_integer()? > r.as_integer()?),
+            Self::Ge => Value::Bool(l.as_integer()? >= r.as_integer()?),
+            _ => {
+                let l = l.as_integer()?;
+                let r = r.as_integer()?;
+                let result = match self {
+                    Self::BitOr => Some(l | r),
+                    Self::BitAnd => Some(l & r),
+                    Self::Add => l.checked_add(r),
+                    Self::Sub => l.checked_sub(r),
+                    Self::Mul => l.checked_mul(r),
+                    Self::Div | Self::Rem if r == 0 => {
+                        return Err(Error::Arithmetic(format!("division by zero in `{}`", self)))
+                    }
+                    Self::Div => l.checked_div(r),
+                    Self::Rem => l.checked_rem(r),
+                    Self::Shl | Self::Shr if !(0..64).contains(&r) => {
+                        return Err(Error::Arithmetic(format!("invalid shift amount {}", r)))
+                    }
+                    Self::Shl => Some(l << r),
+                    Self::Shr => Some(l >> r),
+                    _ => unreachable!("{:?}", self),
+                };
+                Value::Integer(
+                    result.ok_or_else(|| {
+                        Error::Arithmetic(format!("integer overflow in `{}`", self))
+                    })?,
+                )
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// Strings are compared as strings, any other combination of operands as integers.
+fn equals(l: &Value, r: &Value) -> Result<bool> {
+    match (l, r) {
+        (Value::String(l), Value::String(r)) => Ok(l == r),
+        _ => Ok(l.as_integer()? == r.as_integer()?),
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Or => "||",
+            Self::Xor => "^^",
+            Self::And => "&&",
+            Self::BitOr => "|",
+            Self::BitAnd => "&",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Pattern {
+    pub(super) fn new(regex: Regex) -> Self {
+        Self(regex)
+    }
+
+    pub fn as_regex(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}/", self.0.as_str().replace('/', "\\/"))
+    }
+}
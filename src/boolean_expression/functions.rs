@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::{Context, Value};
+use crate::errors::{Error, Result};
+
+/// Function callable from `@if`/`@elif` expressions, e.g. `startswith(PROCESSOR, "PIC")`.
+///
+/// Arguments are evaluated before the call; the context gives access to the definitions and
+/// to the directory of the file being processed.
+pub type Function = Arc<dyn Fn(&[Value], &Context) -> Result<Value> + Send + Sync>;
+
+/// Registry of the functions available in expressions.
+///
+/// [`Functions::default`] contains the built-in functions: `startswith`, `endswith`,
+/// `contains`, `len`, `lower`, `upper`, `int` and `exists`.
+#[derive(Clone)]
+pub struct Functions {
+    functions: HashMap<String, Function>,
+}
+
+impl Functions {
+    /// Creates a registry without any functions, not even the built-in ones.
+    pub fn empty() -> Self {
+        Self {
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Registers a function, replacing any previous function with the same name.
+    pub fn register<S, F>(&mut self, name: S, function: F)
+    where
+        S: Into<String>,
+        F: Fn(&[Value], &Context) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions.insert(name.into(), Arc::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        let mut functions = Self::empty();
+        functions.register("startswith", |args, _| {
+            let [s, prefix] = strings("startswith", args)?;
+            Ok(Value::Bool(s.starts_with(&prefix)))
+        });
+        functions.register("endswith", |args, _| {
+            let [s, suffix] = strings("endswith", args)?;
+            Ok(Value::Bool(s.ends_with(&suffix)))
+        });
+        functions.register("contains", |args, _| {
+            let [s, needle] = strings("contains", args)?;
+            Ok(Value::Bool(s.contains(&needle)))
+        });
+        functions.register("len", |args, _| {
+            let [s] = strings("len", args)?;
+            Ok(Value::Integer(s.chars().count() as i64))
+        });
+        functions.register("lower", |args, _| {
+            let [s] = strings("lower", args)?;
+            Ok(Value::String(s.to_lowercase()))
+        });
+        functions.register("upper", |args, _| {
+            let [s] = strings("upper", args)?;
+            Ok(Value::String(s.to_uppercase()))
+        });
+        functions.register("int", |args, _| {
+            let [value] = arguments("int", args)?;
+            Ok(Value::Integer(value.as_integer()?))
+        });
+        functions.register("exists", |args, context| {
+            let [path] = strings("exists", args)?;
+            let mut path = PathBuf::from(path);
+            if let (true, Some(directory)) = (path.is_relative(), context.directory()) {
+                path = directory.join(path);
+            }
+            Ok(Value::Bool(path.exists()))
+        });
+        functions
+    }
+}
+
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.functions.keys().collect();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+fn arguments<'a, const N: usize>(name: &str, args: &'a [Value]) -> Result<&'a [Value; N]> {
+    args.try_into().map_err(|_| {
+        Error::Type(format!(
+            "{}() takes {} argument(s) but {} were given",
+            name,
+            N,
+            args.len()
+        ))
+    })
+}
+
+fn strings<const N: usize>(name: &str, args: &[Value]) -> Result<[String; N]> {
+    let args = arguments::<N>(name, args)?;
+    let mut strings: [String; N] = [(); N].map(|_| String::new());
+    for (string, arg) in strings.iter_mut().zip(args) {
+        *string = arg.as_string()?;
+    }
+    Ok(strings)
+}
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use super::{BinaryOp, Context, Definitions, Expr, UnaryOp, Value, BUILTIN_FUNCTIONS};
+
+/// Built-in functions whose result only depends on their arguments.
+const PURE_FUNCTIONS: &[&str] = &[
+    "startswith",
+    "endswith",
+    "contains",
+    "len",
+    "lower",
+    "upper",
+    "int",
+];
+
+/// Partial knowledge about definitions: some names are known to be defined (with a value),
+/// some are known to be undefined, everything else is unknown.
+#[derive(Debug, Default, Clone)]
+pub struct KnownDefinitions {
+    defined: Definitions,
+    undefined: HashSet<String>,
+}
+
+impl KnownDefinitions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn define<K, V>(&mut self, name: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let name = name.into();
+        self.undefined.remove(&name);
+        self.defined.insert(name, value.into());
+    }
+
+    pub fn undefine<K: Into<String>>(&mut self, name: K) {
+        let name = name.into();
+        self.defined.remove(&name);
+        self.undefined.insert(name);
+    }
+
+    /// Forgets everything known about `name`.
+    pub fn forget(&mut self, name: &str) {
+        self.defined.remove(name);
+        self.undefined.remove(name);
+    }
+
+    /// `None` if nothing is known about `name`, `Some(None)` if it is known to be undefined.
+    pub fn get(&self, name: &str) -> Option<Option<&str>> {
+        if let Some(value) = self.defined.get(name) {
+            Some(Some(value))
+        } else if self.undefined.contains(name) {
+            Some(None)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_known(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Names known to be defined, with their values.
+    pub fn defined(&self) -> &Definitions {
+        &self.defined
+    }
+}
+
+impl From<Definitions> for KnownDefinitions {
+    /// Everything in `definitions` is known to be defined, nothing is known to be undefined.
+    fn from(definitions: Definitions) -> Self {
+        Self {
+            defined: definitions,
+            undefined: HashSet::new(),
+        }
+    }
+}
+
+impl Expr {
+    /// Partially evaluates the expression with what is known about the definitions.
+    ///
+    /// Known definitions are substituted, constant subexpressions are folded and `&&`, `||`
+    /// and `^^` with a constant operand are reduced, e.g. `defined(A) && B == "1"` becomes
+    /// `B == "1"` when `A` is known to be defined and `0` (false) when it is known to be
+    /// undefined. Reductions treat the expression as a condition: an operand that would fail
+    /// to evaluate (such as an undefined identifier) may be dropped.
+    pub fn simplify(&self, known: &KnownDefinitions) -> Expr {
+        match self {
+            Self::Bool(_) | Self::Integer(_) | Self::String(_) => self.clone(),
+            Self::Identifier(name) => match known.get(name) {
+                Some(Some(value)) => Self::String(value.to_string()),
+                _ => self.clone(),
+            },
+            Self::Defined(name) => match known.get(name) {
+                Some(value) => Self::Bool(value.is_some()),
+                None => self.clone(),
+            },
+            Self::Call(name, args) => {
+                let args = args.iter().map(|arg| arg.simplify(known)).collect();
+                let call = Self::Call(name.clone(), args);
+                if PURE_FUNCTIONS.contains(&name.as_str()) {
+                    call.fold()
+                } else {
+                    call
+                }
+            }
+            Self::Unary(op, expr) => {
+                let expr = expr.simplify(known);
+                match (op, &expr) {
+                    // !!x is only equivalent to x if x is already a boolean
+                    (UnaryOp::Not, Self::Unary(UnaryOp::Not, inner)) if inner.is_boolean() => {
+                        (**inner).clone()
+                    }
+                    _ => Self::Unary(*op, Box::new(expr)).fold(),
+                }
+            }
+            Self::Binary(op, l, r) => simplify_binary(*op, l.simplify(known), r.simplify(known)),
+            Self::Match {
+                expr,
+                pattern,
+                negated,
+            } => Self::Match {
+                expr: Box::new(expr.simplify(known)),
+                pattern: pattern.clone(),
+                negated: *negated,
+            }
+            .fold(),
+        }
+    }
+
+    /// Value of the expression as a condition if it does not depend on any definition.
+    pub fn as_condition(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            Self::Integer(i) => Some(*i != 0),
+            _ => None,
+        }
+    }
+
+    fn is_literal(&self) -> bool {
+        matches!(self, Self::Bool(_) | Self::Integer(_) | Self::String(_))
+    }
+
+    /// Whether the expression always evaluates to a boolean, so that it can replace a
+    /// condition like `1 && expr` without changing its value.
+    fn is_boolean(&self) -> bool {
+        match self {
+            Self::Bool(_) | Self::Defined(_) | Self::Match { .. } => true,
+            Self::Unary(op, _) => *op == UnaryOp::Not,
+            Self::Binary(op, _, _) => matches!(
+                op,
+                BinaryOp::Or
+                    | BinaryOp::Xor
+                    | BinaryOp::And
+                    | BinaryOp::Eq
+                    | BinaryOp::Ne
+                    | BinaryOp::Lt
+                    | BinaryOp::Le
+                    | BinaryOp::Gt
+                    | BinaryOp::Ge
+            ),
+            _ => false,
+        }
+    }
+
+    /// Replaces the expression by its value if all its operands are literals and it evaluates
+    /// without error.
+    fn fold(self) -> Expr {
+        let operands_are_literal = match &self {
+            Self::Call(_, args) => args.iter().all(Self::is_literal),
+            Self::Unary(_, expr) | Self::Match { expr, .. } => expr.is_literal(),
+            Self::Binary(_, l, r) => l.is_literal() && r.is_literal(),
+            _ => false,
+        };
+        if !operands_are_literal {
+            return self;
+        }
+        let definitions = Definitions::new();
+        match self.evaluate(&Context::new(&definitions, &BUILTIN_FUNCTIONS)) {
+            Ok(Value::Bool(b)) => Self::Bool(b),
+            Ok(Value::Integer(i)) => Self::Integer(i),
+            Ok(Value::String(s)) => Self::String(s),
+            Err(_) => self,
+        }
+    }
+}
+
+fn simplify_binary(op: BinaryOp, l: Expr, r: Expr) -> Expr {
+    let (lc, rc) = (l.as_condition(), r.as_condition());
+    let reduced = match op {
+        BinaryOp::And | BinaryOp::Or => {
+            // a constant that decides the result regardless of the other operand
+            let absorbing = op == BinaryOp::Or;
+            if lc == Some(absorbing) || rc == Some(absorbing) {
+                Some(Expr::Bool(absorbing))
+            } else if lc == Some(!absorbing) && r.is_boolean() {
+                Some(r.clone())
+            } else if rc == Some(!absorbing) && l.is_boolean() {
+                Some(l.clone())
+            } else {
+                None
+            }
+        }
+        BinaryOp::Xor => match (lc, rc) {
+            (Some(false), _) if r.is_boolean() => Some(r.clone()),
+            (_, Some(false)) if l.is_boolean() => Some(l.clone()),
+            (Some(true), _) if !r.is_literal() => Some(negate(r.clone())),
+            (_, Some(true)) if !l.is_literal() => Some(negate(l.clone())),
+            _ => None,
+        },
+        _ => None,
+    };
+    reduced.unwrap_or_else(|| Expr::Binary(op, Box::new(l), Box::new(r)).fold())
+}
+
+fn negate(expr: Expr) -> Expr {
+    match expr {
+        Expr::Unary(UnaryOp::Not, inner) if inner.is_boolean() => *inner,
+        expr => Expr::Unary(UnaryOp::Not, Box::new(expr)),
+    }
+}
@@ -0,0 +1,138 @@
+//! Cache of included files shared by preprocessor runs.
+//!
+//! Sleigh modules have many `.slaspec` files including the same `.sinc` files. The cache keeps
+//! the lines of every file read, and the output of every include along with the definitions
+//! it consulted, so that including the file again while these definitions have the same values
+//! reuses the output instead of preprocessing the file again.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::dependencies::Dependencies;
+use crate::diagnostic::Diagnostic;
+use crate::graph::IncludeGraph;
+use crate::inactive::InactiveRegions;
+use crate::location::Location;
+use crate::Definitions;
+
+/// Cache to share between [`SleighPreprocessor`](crate::SleighPreprocessor) runs, clones share
+/// the same cache.
+///
+/// Files are assumed not to change while the cache is in use, [`IncludeCache::clear`] forgets
+/// everything read so far.
+#[derive(Clone, Default)]
+pub struct IncludeCache {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    files: Mutex<HashMap<PathBuf, Arc<Vec<String>>>>,
+    outputs: Mutex<HashMap<OutputKey, Vec<Arc<IncludeOutput>>>>,
+    reused_outputs: AtomicUsize,
+}
+
+/// Everything but the definitions the output of an include depends on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct OutputKey {
+    pub(crate) path: PathBuf,
+    pub(crate) compatible: bool,
+    pub(crate) indented_directives: bool,
+    pub(crate) include_dirs: Vec<PathBuf>,
+}
+
+/// What including a file did.
+#[derive(Debug)]
+pub(crate) struct IncludeOutput {
+    /// Definitions looked at before the include changed them, with their values.
+    pub(crate) consulted: Vec<(String, Option<String>)>,
+    /// Definitions the include changed, with their values afterwards.
+    pub(crate) written: Vec<(String, Option<String>)>,
+    pub(crate) text: String,
+    /// Locations with output line numbers counted from the first line of the include.
+    pub(crate) locations: Vec<Location>,
+    /// Diagnostics with overall line numbers counted from the include directive, and
+    /// without the includes down to the included file.
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// Files the include read and looked up in vain, itself included.
+    pub(crate) dependencies: Dependencies,
+    /// Includes seen in the include and below.
+    pub(crate) include_graph: IncludeGraph,
+    pub(crate) inactive_regions: InactiveRegions,
+}
+
+impl IncludeCache {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of times the output of an include was reused instead of preprocessing the file.
+    pub fn reused_outputs(&self) -> usize {
+        self.inner.reused_outputs.load(Ordering::Relaxed)
+    }
+
+    pub fn clear(&self) {
+        self.inner.files.lock().unwrap().clear();
+        self.inner.outputs.lock().unwrap().clear();
+    }
+
+    /// Lines of the file, read on first use.
+    pub(crate) fn lines(&self, path: &Path) -> io::Result<Arc<Vec<String>>> {
+        if let Some(lines) = self.inner.files.lock().unwrap().get(path) {
+            return Ok(Arc::clone(lines));
+        }
+        let lines = Arc::new(read_lines(path)?);
+        self.inner
+            .files
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Arc::clone(&lines));
+        Ok(lines)
+    }
+
+    /// Output of a previous include matching the current definitions.
+    pub(crate) fn output(
+        &self,
+        key: &OutputKey,
+        definitions: &Definitions,
+    ) -> Option<Arc<IncludeOutput>> {
+        let outputs = self.inner.outputs.lock().unwrap();
+        let output = outputs.get(key)?.iter().find(|output| {
+            output
+                .consulted
+                .iter()
+                .all(|(name, value)| definitions.get(name) == value.as_ref())
+        })?;
+        self.inner.reused_outputs.fetch_add(1, Ordering::Relaxed);
+        Some(Arc::clone(output))
+    }
+
+    pub(crate) fn insert_output(&self, key: OutputKey, output: IncludeOutput) {
+        self.inner
+            .outputs
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push(Arc::new(output));
+    }
+}
+
+impl fmt::Debug for IncludeCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncludeCache")
+            .field("files", &self.inner.files.lock().unwrap().len())
+            .field("outputs", &self.inner.outputs.lock().unwrap().len())
+            .field("reused_outputs", &self.reused_outputs())
+            .finish()
+    }
+}
+
+pub(crate) fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    BufReader::new(File::open(path)?).lines().collect()
+}
@@ -4,6 +4,8 @@ pub(crate) struct ConditionalHelper {
     saw_else: bool,
     handled: bool,
     copy: bool,
+    /// Line of the directive starting the current branch.
+    line_no: usize,
 }
 
 impl ConditionalHelper {
@@ -13,6 +15,7 @@ impl ConditionalHelper {
             saw_else,
             handled,
             copy,
+            line_no: 0,
         }
     }
 
@@ -43,4 +46,12 @@ impl ConditionalHelper {
     pub(crate) fn set_copy(&mut self, copy: bool) {
         self.copy = copy;
     }
+
+    pub(crate) fn line_no(&self) -> usize {
+        self.line_no
+    }
+
+    pub(crate) fn set_line_no(&mut self, line_no: usize) {
+        self.line_no = line_no;
+    }
 }
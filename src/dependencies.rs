@@ -0,0 +1,76 @@
+//! Files a preprocessed root depends on, and their Makefile rule.
+
+use std::path::{Path, PathBuf};
+
+/// Files read while preprocessing, in the order they were first read, the root first even
+/// when its source is given, and files looked up
+/// for an `@include` which did not exist. Creating one of the latter may change the output.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Dependencies {
+    files: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+}
+
+impl Dependencies {
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn missing(&self) -> &[PathBuf] {
+        &self.missing
+    }
+
+    pub(crate) fn add_file(&mut self, path: &Path) {
+        if !self.files.iter().any(|file| file == path) {
+            self.files.push(path.to_path_buf());
+        }
+    }
+
+    pub(crate) fn add_missing(&mut self, path: &Path) {
+        if !self.missing.iter().any(|file| file == path) {
+            self.missing.push(path.to_path_buf());
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &Dependencies) {
+        for file in &other.files {
+            self.add_file(file);
+        }
+        for file in &other.missing {
+            self.add_missing(file);
+        }
+    }
+
+    /// Makefile rule making `target` depend on every file, as written by `gcc -MD -MP`: each
+    /// dependency but the root also gets a rule without prerequisites, so that make does not
+    /// fail once it is deleted and rebuilds the target while a missing file is still missing.
+    /// Ninja reads the same format.
+    pub fn to_makefile(&self, target: impl AsRef<Path>) -> String {
+        let mut rule = format!("{}:", escape(target.as_ref()));
+        for file in self.files.iter().chain(&self.missing) {
+            rule.push_str(" \\\n  ");
+            rule.push_str(&escape(file));
+        }
+        rule.push('\n');
+        // the root, read first
+        for file in self.files.iter().skip(1).chain(&self.missing) {
+            rule.push_str(&format!("\n{}:\n", escape(file)));
+        }
+        rule
+    }
+}
+
+/// Escapes the characters make treats specially in a file name.
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::location::IncludeSite;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Kind of problem a diagnostic reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DiagnosticKind {
+    /// Indented line looking like a directive which does not exist.
+    UnrecognizedDirective,
+    /// Indented directive, which Ghidra copies as text.
+    IndentedDirective,
+}
+
+impl DiagnosticKind {
+    /// Short name of the kind, e.g. `indented_directive`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UnrecognizedDirective => "unrecognized_directive",
+            Self::IndentedDirective => "indented_directive",
+        }
+    }
+}
+
+/// Problem found while preprocessing which does not stop it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Diagnostic {
+    severity: Severity,
+    kind: DiagnosticKind,
+    message: String,
+    path: PathBuf,
+    line_no: usize,
+    overall_line_no: usize,
+    line: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    includes: Vec<IncludeSite>,
+}
+
+impl Diagnostic {
+    pub(crate) fn warning<S, P>(
+        kind: DiagnosticKind,
+        message: S,
+        path: P,
+        line_no: usize,
+        overall_line_no: usize,
+        line: S,
+    ) -> Self
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        Self {
+            severity: Severity::Warning,
+            kind,
+            message: message.into(),
+            path: path.into(),
+            line_no,
+            overall_line_no,
+            line: line.into(),
+            includes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_overall_line_no(mut self, overall_line_no: usize) -> Self {
+        self.overall_line_no = overall_line_no;
+        self
+    }
+
+    pub(crate) fn with_includes(mut self, includes: Vec<IncludeSite>) -> Self {
+        self.includes = includes;
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn kind(&self) -> DiagnosticKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn line_no(&self) -> usize {
+        self.line_no
+    }
+
+    pub fn overall_line_no(&self) -> usize {
+        self.overall_line_no
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Includes through which the file was included, the one in the root first.
+    pub fn includes(&self) -> &[IncludeSite] {
+        &self.includes
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} at {}:{}({}): {}",
+            self.severity,
+            self.message,
+            self.path.display(),
+            self.line_no,
+            self.overall_line_no,
+            self.line
+        )
+    }
+}
@@ -0,0 +1,354 @@
+//! Recognition of directive lines.
+//!
+//! Lines are scanned by hand, once, instead of being matched against a regular expression per
+//! directive: the keyword after `@` selects how the arguments are parsed.
+
+use std::ops::Range;
+
+use crate::diagnostic::DiagnosticKind;
+
+const KEYWORDS: &[&str] = &[
+    "include", "define", "undef", "ifdef", "ifndef", "if", "elif", "else", "endif",
+];
+
+/// Preprocessor directive of a line, as recognized by the preprocessor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Directive<'a> {
+    /// `@include "path"`
+    Include(&'a str),
+    /// `@define NAME "value"`, `@define NAME value` or `@define NAME`; quoted values are not
+    /// unescaped yet.
+    Define {
+        name: &'a str,
+        value: Option<&'a str>,
+        quoted: bool,
+    },
+    Undef(&'a str),
+    Ifdef(&'a str),
+    Ifndef(&'a str),
+    If(&'a str),
+    Elif(&'a str),
+    Else,
+    Endif,
+}
+
+impl<'a> Directive<'a> {
+    /// Recognizes the directive of a line without its comment, `None` if it is malformed or
+    /// unknown. Whitespace may precede the `@`.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let (keyword, arguments) = split_keyword(line)?;
+        match keyword {
+            "include" => quoted(skip_whitespace(arguments)?).map(Self::Include),
+            "define" => {
+                let (name, rest) = split_name(skip_whitespace(arguments)?);
+                if name.is_empty() {
+                    return None;
+                }
+                let value = match skip_whitespace(rest) {
+                    Some(value) => value,
+                    None if rest.is_empty() => "",
+                    None => return None,
+                };
+                if value.is_empty() {
+                    Some(Self::Define {
+                        name,
+                        value: None,
+                        quoted: false,
+                    })
+                } else if let Some(value) = quoted(value) {
+                    Some(Self::Define {
+                        name,
+                        value: Some(value),
+                        quoted: true,
+                    })
+                } else {
+                    let value = value.trim_end();
+                    if value.contains(char::is_whitespace) {
+                        return None;
+                    }
+                    Some(Self::Define {
+                        name,
+                        value: Some(value),
+                        quoted: false,
+                    })
+                }
+            }
+            "undef" => single_name(arguments).map(Self::Undef),
+            "ifdef" => single_name(arguments).map(Self::Ifdef),
+            "ifndef" => single_name(arguments).map(Self::Ifndef),
+            "if" => skip_whitespace(arguments).map(Self::If),
+            "elif" => skip_whitespace(arguments).map(Self::Elif),
+            "else" if arguments.trim_start().is_empty() => Some(Self::Else),
+            "endif" if arguments.trim_start().is_empty() => Some(Self::Endif),
+            _ => None,
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits `@keyword rest`, possibly indented, after the keyword.
+fn split_keyword(line: &str) -> Option<(&str, &str)> {
+    Some(split_name(line.trim_start().strip_prefix('@')?))
+}
+
+/// Splits the name at the start of `s` from the rest, the name is empty if there is none.
+fn split_name(s: &str) -> (&str, &str) {
+    s.split_at(s.find(|c| !is_name_char(c)).unwrap_or(s.len()))
+}
+
+/// Removes the leading whitespace of `s`, `None` if there is none.
+fn skip_whitespace(s: &str) -> Option<&str> {
+    let trimmed = s.trim_start();
+    if trimmed.len() < s.len() {
+        Some(trimmed)
+    } else {
+        None
+    }
+}
+
+/// Contents of `"..."` followed by nothing but whitespace.
+fn quoted(s: &str) -> Option<&str> {
+    let s = s.trim_end();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        Some(&s[1..s.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// The name of `@undef NAME` and the like, alone on the line.
+fn single_name(arguments: &str) -> Option<&str> {
+    let (name, rest) = split_name(skip_whitespace(arguments)?);
+    if name.is_empty() || !rest.trim_start().is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Whether the line only holds a comment.
+pub(crate) fn is_full_line_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// Text of the directive on `line` without its comment, `None` if the line is not a
+/// directive.
+///
+/// Directives start at the beginning of the line, or if `indented` is set after whitespace
+/// provided their name is known, so that other indented `@` lines stay text.
+pub(crate) fn directive_text(line: &str, indented: bool) -> Option<&str> {
+    let is_directive = line.starts_with('@')
+        || (indented && indented_keyword(line).is_some_and(|k| KEYWORDS.contains(&k)));
+    if is_directive {
+        Some(&line[..comment_start(line).unwrap_or(line.len())])
+    } else {
+        None
+    }
+}
+
+/// Kind and message of the warning about a directive copied as text.
+pub(crate) fn misplaced_directive(
+    line: &str,
+    indented: bool,
+) -> Option<(DiagnosticKind, &'static str)> {
+    match indented_keyword(line) {
+        Some(keyword) if !KEYWORDS.contains(&keyword) => Some((
+            DiagnosticKind::UnrecognizedDirective,
+            "unrecognized indented preprocessor directive copied as text",
+        )),
+        Some(_) if !indented => Some((
+            DiagnosticKind::IndentedDirective,
+            "indented preprocessor directive copied as text",
+        )),
+        _ => None,
+    }
+}
+
+fn indented_keyword(line: &str) -> Option<&str> {
+    skip_whitespace(line)?;
+    split_keyword(line).map(|(keyword, _)| keyword)
+}
+
+/// Offset of the comment ending a directive line. A `#` in a quoted string or in the
+/// pattern of a match operator does not start a comment.
+pub(crate) fn comment_start(line: &str) -> Option<usize> {
+    Tokenizer::new(line)
+        .find(|token| token.kind == TokenKind::Comment)
+        .map(|token| token.span.start)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TokenKind {
+    Whitespace,
+    /// Name, keyword or number, including the `@` of a directive name.
+    Word,
+    /// Double-quoted string with its quotes, `\` escaping the next character.
+    String,
+    /// `/pattern/` following `=~` or `!~`, with its slashes.
+    Pattern,
+    /// Any other character, or a quote or slash which is not closed on the line.
+    Punctuation,
+    /// `#` and the rest of the line.
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    pub(crate) span: Range<usize>,
+}
+
+/// Splits a directive line into tokens covering all of it.
+#[derive(Debug)]
+pub(crate) struct Tokenizer<'a> {
+    line: &'a str,
+    position: usize,
+    /// The previous token, whitespace aside, is a match operator.
+    after_match_operator: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub(crate) fn new(line: &'a str) -> Self {
+        Self {
+            line,
+            position: 0,
+            after_match_operator: false,
+        }
+    }
+
+    /// Length of a literal delimited by `delimiter` at the start of `rest`, `None` if it is
+    /// not closed on the line.
+    fn delimited(rest: &str, delimiter: char) -> Option<usize> {
+        let mut escaped = false;
+        for (i, c) in rest.char_indices().skip(1) {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c == delimiter => return Some(i + 1),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let rest = &self.line[self.position..];
+        let c = rest.chars().next()?;
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let (kind, len) = match c {
+            '#' => (TokenKind::Comment, rest.len()),
+            '"' => match Self::delimited(rest, '"') {
+                Some(len) => (TokenKind::String, len),
+                None => (TokenKind::Punctuation, 1),
+            },
+            '/' if self.after_match_operator => match Self::delimited(rest, '/') {
+                Some(len) => (TokenKind::Pattern, len),
+                None => (TokenKind::Punctuation, 1),
+            },
+            c if c.is_whitespace() => (
+                TokenKind::Whitespace,
+                rest.find(|c: char| !c.is_whitespace())
+                    .unwrap_or(rest.len()),
+            ),
+            c if is_word(c) || c == '@' => (
+                TokenKind::Word,
+                1 + rest[1..].find(|c| !is_word(c)).unwrap_or(rest.len() - 1),
+            ),
+            '=' | '!' if rest[1..].starts_with('~') => (TokenKind::Punctuation, 2),
+            c => (TokenKind::Punctuation, c.len_utf8()),
+        };
+        if kind != TokenKind::Whitespace {
+            self.after_match_operator =
+                len == 2 && (rest.starts_with("=~") || rest.starts_with("!~"));
+        }
+        let span = self.position..self.position + len;
+        self.position = span.end;
+        Some(Token { kind, span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Directive;
+
+    fn define<'a>(name: &'a str, value: Option<&'a str>, quoted: bool) -> Option<Directive<'a>> {
+        Some(Directive::Define {
+            name,
+            value,
+            quoted,
+        })
+    }
+
+    #[test]
+    fn parse_directives() {
+        assert_eq!(
+            Directive::parse(r#"@include "a b/c.sinc"  "#),
+            Some(Directive::Include("a b/c.sinc"))
+        );
+        assert_eq!(
+            Directive::parse(r#"@define A "x" "y""#),
+            define("A", Some(r#"x" "y"#), true)
+        );
+        assert_eq!(
+            Directive::parse(r#"@define A """#),
+            define("A", Some(""), true)
+        );
+        assert_eq!(
+            Directive::parse("@define A\tvalue "),
+            define("A", Some("value"), false)
+        );
+        assert_eq!(
+            Directive::parse(r#"@define A ""#),
+            define("A", Some("\""), false)
+        );
+        assert_eq!(Directive::parse("@define A  "), define("A", None, false));
+        assert_eq!(Directive::parse("@define A"), define("A", None, false));
+        assert_eq!(Directive::parse("@undef A "), Some(Directive::Undef("A")));
+        assert_eq!(Directive::parse("@ifdef A"), Some(Directive::Ifdef("A")));
+        assert_eq!(Directive::parse("@ifndef A"), Some(Directive::Ifndef("A")));
+        assert_eq!(
+            Directive::parse("@if  A == \"1\" "),
+            Some(Directive::If("A == \"1\" "))
+        );
+        assert_eq!(Directive::parse("@elif B"), Some(Directive::Elif("B")));
+        assert_eq!(Directive::parse("@else "), Some(Directive::Else));
+        assert_eq!(Directive::parse("@endif"), Some(Directive::Endif));
+        assert_eq!(Directive::parse("  @endif"), Some(Directive::Endif));
+    }
+
+    #[test]
+    fn parse_malformed_directives() {
+        for line in &[
+            "",
+            "text",
+            "@",
+            "@include",
+            "@include file.sinc",
+            "@include\"file.sinc\"",
+            "@includes \"file.sinc\"",
+            "@define",
+            "@define A b c",
+            "@define A-B c",
+            "@define \"A\" b",
+            "@undef",
+            "@undef A B",
+            "@ifdef A-B",
+            "@ifdefA",
+            "@if",
+            "@if(A)",
+            "@else A",
+            "@endif;",
+            "@endifé",
+            "@unknown",
+        ] {
+            assert_eq!(Directive::parse(line), None, "{:?}", line);
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use std::{fmt, io, path::PathBuf};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::location::IncludeSite;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,6 +12,91 @@ pub enum Error {
     Preprocessor(PreprocessorError),
     Parsing(String),
     NotDefined(String),
+    Type(String),
+    Arithmetic(String),
+    UnknownFunction(String),
+}
+
+/// What went wrong, the kind of a preprocessor error being that of the error it reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ErrorKind {
+    Io,
+    /// Preprocessing failed because of errors reported before.
+    Preprocessor,
+    Parsing,
+    NotDefined,
+    Type,
+    Arithmetic,
+    UnknownFunction,
+    MissingInclude,
+    RecursiveInclude,
+    /// `@elif`, `@else` or `@endif` out of place, or `@endif` missing.
+    UnbalancedConditional,
+    UnrecognizedDirective,
+    InvalidEscape,
+    /// File specialized differently where it is included again.
+    InconsistentSpecialization,
+}
+
+impl ErrorKind {
+    /// Short name of the kind, e.g. `not_defined`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Io => "io",
+            Self::Preprocessor => "preprocessor",
+            Self::Parsing => "parsing",
+            Self::NotDefined => "not_defined",
+            Self::Type => "type",
+            Self::Arithmetic => "arithmetic",
+            Self::UnknownFunction => "unknown_function",
+            Self::MissingInclude => "missing_include",
+            Self::RecursiveInclude => "recursive_include",
+            Self::UnbalancedConditional => "unbalanced_conditional",
+            Self::UnrecognizedDirective => "unrecognized_directive",
+            Self::InvalidEscape => "invalid_escape",
+            Self::InconsistentSpecialization => "inconsistent_specialization",
+        }
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(_) => ErrorKind::Io,
+            Self::Preprocessor(e) => e.kind,
+            Self::Parsing(_) => ErrorKind::Parsing,
+            Self::NotDefined(_) => ErrorKind::NotDefined,
+            Self::Type(_) => ErrorKind::Type,
+            Self::Arithmetic(_) => ErrorKind::Arithmetic,
+            Self::UnknownFunction(_) => ErrorKind::UnknownFunction,
+        }
+    }
+}
+
+/// Serialized as its kind, its message and, for preprocessor errors, their position.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let preprocessor = match self {
+            Self::Preprocessor(e) => Some(e),
+            _ => None,
+        };
+        let mut error = serializer.serialize_struct("Error", 3)?;
+        error.serialize_field("kind", &self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("preprocessor", &preprocessor)?;
+        error.end()
+    }
 }
 
 impl From<io::Error> for Error {
@@ -29,6 +118,9 @@ impl std::error::Error for Error {
             Error::Preprocessor(ref e) => Some(e),
             Self::Parsing(_) => None,
             Self::NotDefined(_) => None,
+            Self::Type(_) => None,
+            Self::Arithmetic(_) => None,
+            Self::UnknownFunction(_) => None,
         }
     }
 }
@@ -40,6 +132,9 @@ impl fmt::Display for Error {
             Error::Preprocessor(ref e) => write!(f, "Preprocessor error: {}", e),
             Self::Parsing(msg) => write!(f, "Parsing error: {}", msg),
             Self::NotDefined(identifier) => write!(f, "Identifier \"{}\" not defined", identifier),
+            Self::Type(msg) => write!(f, "Type error: {}", msg),
+            Self::Arithmetic(msg) => write!(f, "Arithmetic error: {}", msg),
+            Self::UnknownFunction(name) => write!(f, "Function \"{}\" not defined", name),
         }
     }
 }
@@ -60,21 +155,50 @@ impl PartialEq for Error {
                 Self::NotDefined(r) => l == r,
                 _ => false,
             },
+            Self::Type(l) => match other {
+                Self::Type(r) => l == r,
+                _ => false,
+            },
+            Self::Arithmetic(l) => match other {
+                Self::Arithmetic(r) => l == r,
+                _ => false,
+            },
+            Self::UnknownFunction(l) => match other {
+                Self::UnknownFunction(r) => l == r,
+                _ => false,
+            },
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PreprocessorError {
+    kind: ErrorKind,
     message: String,
     path: PathBuf,
     line_no: usize,
     overall_line_no: usize,
     line: String,
+    /// Boxed to keep results small.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    origin: Option<Box<Origin>>,
+}
+
+/// Where in the processed files an error was raised.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct Origin {
+    /// Path of the file, `path` being its name only.
+    file_path: PathBuf,
+    column: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    includes: Vec<IncludeSite>,
 }
 
 impl PreprocessorError {
     pub(crate) fn new<S, P>(
+        kind: ErrorKind,
         message: S,
         path: P,
         line_no: usize,
@@ -89,13 +213,94 @@ impl PreprocessorError {
         let path = path.into();
         let line = line.into();
         Self {
+            kind,
             message,
             path,
             line_no,
             overall_line_no,
             line,
+            origin: None,
         }
     }
+
+    /// Error at a line of the file at `path`, which is read on its own rather than included.
+    pub(crate) fn in_file<S: Into<String>>(
+        kind: ErrorKind,
+        message: S,
+        path: &Path,
+        line_no: usize,
+        overall_line_no: usize,
+        line: S,
+    ) -> Self {
+        let file_name = path.file_name().map(PathBuf::from).unwrap_or_default();
+        let mut error = Self::new(kind, message, file_name, line_no, overall_line_no, line);
+        error.locate(path, &[], None);
+        error
+    }
+
+    /// Completes the position of an error raised in `file_path`, unless it is known. The
+    /// column is the one of `line` in `source_line`.
+    pub(crate) fn locate(
+        &mut self,
+        file_path: &Path,
+        includes: &[IncludeSite],
+        source_line: Option<&str>,
+    ) {
+        if self.origin.is_some() {
+            return;
+        }
+        let column = source_line
+            .filter(|_| !self.line.is_empty())
+            .and_then(|source_line| {
+                let start = source_line.find(&self.line)?;
+                Some(source_line[..start].chars().count() + 1)
+            });
+        self.origin = Some(Box::new(Origin {
+            file_path: file_path.to_path_buf(),
+            column,
+            includes: includes.to_vec(),
+        }));
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line_no(&self) -> usize {
+        self.line_no
+    }
+
+    pub fn overall_line_no(&self) -> usize {
+        self.overall_line_no
+    }
+
+    /// Text the error is about, a part of the line or the whole line.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Path of the file, if the error was raised while preprocessing a file.
+    pub fn file_path(&self) -> Option<&Path> {
+        self.origin
+            .as_ref()
+            .map(|origin| origin.file_path.as_path())
+    }
+
+    /// Column of [`line`](Self::line) in the line, counted in characters from 1.
+    pub fn column(&self) -> Option<usize> {
+        self.origin.as_ref().and_then(|origin| origin.column)
+    }
+
+    /// Includes through which the file was included, the one in the root first.
+    pub fn includes(&self) -> &[IncludeSite] {
+        self.origin
+            .as_ref()
+            .map_or(&[], |origin| origin.includes.as_slice())
+    }
 }
 
 impl std::error::Error for PreprocessorError {}
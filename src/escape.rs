@@ -0,0 +1,135 @@
+use std::fmt;
+use std::str::CharIndices;
+
+/// Invalid escape sequence found by [`unescape`].
+#[derive(Debug, PartialEq)]
+pub struct InvalidEscape {
+    start: usize,
+    end: usize,
+    sequence: String,
+}
+
+impl InvalidEscape {
+    fn new(input: &str, start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            sequence: input[start..end].to_string(),
+        }
+    }
+
+    /// Byte offset of the escape sequence in the input.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset just past the escape sequence in the input.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+impl std::error::Error for InvalidEscape {}
+
+impl fmt::Display for InvalidEscape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid escape sequence `{}`", self.sequence)
+    }
+}
+
+/// Decodes the escape sequences of a quoted string, i.e. the text between the quotes.
+///
+/// Supports the same escapes as Java string literals: `\b`, `\t`, `\n`, `\f`, `\r`, `\"`,
+/// `\'`, `\\`, octal escapes from `\0` to `\377` and `\uXXXX` (surrogate pairs are combined).
+pub fn unescape(input: &str) -> Result<String, InvalidEscape> {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.char_indices();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        let c = match chars.next() {
+            Some((_, 'b')) => '\u{8}',
+            Some((_, 't')) => '\t',
+            Some((_, 'n')) => '\n',
+            Some((_, 'f')) => '\u{c}',
+            Some((_, 'r')) => '\r',
+            Some((_, c @ '"')) | Some((_, c @ '\'')) | Some((_, c @ '\\')) => c,
+            Some((_, 'u')) => unescape_unicode(input, start, &mut chars)?,
+            Some((_, c @ '0'..='7')) => unescape_octal(c, &mut chars),
+            Some((i, c)) => return Err(InvalidEscape::new(input, start, i + c.len_utf8())),
+            None => return Err(InvalidEscape::new(input, start, input.len())),
+        };
+        output.push(c);
+    }
+    Ok(output)
+}
+
+/// Escapes `input` so that [`unescape`] restores it; the result can be put between quotes.
+pub fn escape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\u{8}' => output.push_str("\\b"),
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\u{c}' => output.push_str("\\f"),
+            '\r' => output.push_str("\\r"),
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+fn unescape_octal(first: char, chars: &mut CharIndices) -> char {
+    let max_digits = if first <= '3' { 3 } else { 2 };
+    let mut value = first.to_digit(8).unwrap();
+    for _ in 1..max_digits {
+        match chars.clone().next() {
+            Some((_, c @ '0'..='7')) => {
+                value = value * 8 + c.to_digit(8).unwrap();
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    char::from(value as u8)
+}
+
+fn unescape_unicode(
+    input: &str,
+    start: usize,
+    chars: &mut CharIndices,
+) -> Result<char, InvalidEscape> {
+    let high = code_unit(input, start, chars)?;
+    if !(0xD800..0xDC00).contains(&high) {
+        return char::from_u32(high).ok_or_else(|| InvalidEscape::new(input, start, start + 6));
+    }
+    // a high surrogate must be followed by an escaped low surrogate
+    let mut lookahead = chars.clone();
+    if let (Some((low_start, '\\')), Some((_, 'u'))) = (lookahead.next(), lookahead.next()) {
+        let low = code_unit(input, low_start, &mut lookahead)?;
+        if (0xDC00..0xE000).contains(&low) {
+            *chars = lookahead;
+            return Ok(char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).unwrap());
+        }
+    }
+    Err(InvalidEscape::new(input, start, start + 6))
+}
+
+/// Reads the four hexadecimal digits of an `\uXXXX` escape starting at `start`.
+fn code_unit(input: &str, start: usize, chars: &mut CharIndices) -> Result<u32, InvalidEscape> {
+    let mut value = 0;
+    for _ in 0..4 {
+        match chars.next() {
+            Some((_, c)) if c.is_ascii_hexdigit() => value = value * 16 + c.to_digit(16).unwrap(),
+            Some((i, c)) => return Err(InvalidEscape::new(input, start, i + c.len_utf8())),
+            None => return Err(InvalidEscape::new(input, start, input.len())),
+        }
+    }
+    Ok(value)
+}
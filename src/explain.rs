@@ -0,0 +1,95 @@
+//! Why a source line is copied to the output or not.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::location::IncludeSite;
+
+/// State of the conditionals enclosing a line each time it was read.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Explanation {
+    pub path: PathBuf,
+    pub line_no: usize,
+    /// Includes through which the file was included, the one in the root first.
+    pub includes: Vec<IncludeSite>,
+    /// Whether the line is copied, which is the case when the last branch of every
+    /// conditional is taken.
+    pub active: bool,
+    /// Enclosing conditionals, the outermost first, including those around the includes.
+    pub conditionals: Vec<Conditional>,
+}
+
+/// `@if`, `@ifdef` or `@ifndef` and the `@elif` and `@else` directives following it, up to
+/// the branch enclosing the line.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Conditional {
+    pub path: PathBuf,
+    pub branches: Vec<Branch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Branch {
+    pub line_no: usize,
+    /// The directive, e.g. `@elif defined(ARCH)`.
+    pub directive: String,
+    /// Value of the condition, none for `@else` and for expressions which are not evaluated
+    /// because an earlier branch is taken.
+    pub value: Option<bool>,
+    /// Whether the lines of the branch are copied, provided the enclosing conditionals
+    /// copy them.
+    pub taken: bool,
+    /// Definitions the condition looks at, with their values when it was read.
+    pub definitions: Vec<(String, Option<String>)>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{} is {}",
+            self.path.display(),
+            self.line_no,
+            if self.active { "active" } else { "inactive" }
+        )?;
+        for site in self.includes.iter().rev() {
+            writeln!(
+                f,
+                "  in file included from {}:{}",
+                site.path.display(),
+                site.line_no
+            )?;
+        }
+        for (depth, conditional) in self.conditionals.iter().enumerate() {
+            for branch in &conditional.branches {
+                write!(f, "{}", "  ".repeat(depth + 1))?;
+                branch.fmt(f, &conditional.path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>, path: &Path) -> fmt::Result {
+        write!(f, "{}:{}: {}", path.display(), self.line_no, self.directive)?;
+        if let Some(value) = self.value {
+            write!(f, " = {}", value)?;
+        }
+        write!(f, ", {}", if self.taken { "taken" } else { "not taken" })?;
+        let definitions: Vec<_> = self
+            .definitions
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("{} = {:?}", name, value),
+                None => format!("{} undefined", name),
+            })
+            .collect();
+        if !definitions.is_empty() {
+            write!(f, " ({})", definitions.join(", "))?;
+        }
+        writeln!(f)
+    }
+}
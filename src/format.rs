@@ -0,0 +1,111 @@
+//! Formatter of preprocessor directives.
+//!
+//! Directives are indented by their nesting depth and written with single spaces and quoted
+//! `@define` values, except unquoted values with a backslash or a quote which are kept as
+//! written. Lines which are not directives, unknown directives and directives that do not
+//! parse are left untouched.
+
+use crate::directive::Directive;
+use crate::syntax::{DirectiveKind, DirectiveNode, Node, SyntaxTree};
+
+#[derive(Debug, Default, Clone)]
+pub struct Formatter {
+    indent: usize,
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of spaces per nesting level of a directive, none by default.
+    ///
+    /// Only readers which accept indented directives can process the output of a non-zero
+    /// indentation.
+    pub fn with_indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    pub fn format(&self, source: &str) -> String {
+        let tree = SyntaxTree::parse(source);
+        let mut output = String::with_capacity(source.len());
+        self.format_nodes(&tree, tree.nodes(), 0, &mut output);
+        output
+    }
+
+    /// Whether formatting `source` does not change it.
+    pub fn is_formatted(&self, source: &str) -> bool {
+        self.format(source) == source
+    }
+
+    fn format_nodes(&self, tree: &SyntaxTree, nodes: &[Node], depth: usize, output: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(span) => output.push_str(tree.text(span)),
+                Node::Directive(directive) => self.format_directive(tree, directive, depth, output),
+                Node::Conditional(conditional) => {
+                    for branch in &conditional.branches {
+                        self.format_directive(tree, &branch.directive, depth, output);
+                        self.format_nodes(tree, &branch.body, depth + 1, output);
+                    }
+                    if let Some(endif) = &conditional.endif {
+                        self.format_directive(tree, endif, depth, output);
+                    }
+                }
+            }
+        }
+    }
+
+    fn format_directive(
+        &self,
+        tree: &SyntaxTree,
+        node: &DirectiveNode,
+        depth: usize,
+        output: &mut String,
+    ) {
+        let line = tree.text(&node.span);
+        let directive = format!("@{} {}", tree.text(&node.name), tree.text(&node.arguments));
+        let formatted = match Directive::parse(&directive) {
+            _ if node.kind == DirectiveKind::Unknown => None,
+            Some(Directive::Include(path)) => Some(format!("@include \"{}\"", path)),
+            Some(Directive::Define {
+                name,
+                value: Some(value),
+                quoted,
+            }) => {
+                // Ghidra does not unescape quoted values, quoting `a\b` would change its meaning
+                if quoted || !value.contains(&['\\', '"'][..]) {
+                    Some(format!("@define {} \"{}\"", name, value))
+                } else {
+                    Some(format!("@define {} {}", name, value))
+                }
+            }
+            Some(Directive::Define {
+                name, value: None, ..
+            }) => Some(format!("@define {}", name)),
+            Some(Directive::Undef(name)) => Some(format!("@undef {}", name)),
+            Some(Directive::Ifdef(name)) => Some(format!("@ifdef {}", name)),
+            Some(Directive::Ifndef(name)) => Some(format!("@ifndef {}", name)),
+            Some(Directive::If(expression)) => Some(format!("@if {}", expression.trim())),
+            Some(Directive::Elif(expression)) => Some(format!("@elif {}", expression.trim())),
+            Some(Directive::Else) => Some("@else".to_string()),
+            Some(Directive::Endif) => Some("@endif".to_string()),
+            None => None,
+        };
+        let formatted = match formatted {
+            Some(formatted) => formatted,
+            None => {
+                output.push_str(line);
+                return;
+            }
+        };
+        output.push_str(&" ".repeat(self.indent * depth));
+        output.push_str(&formatted);
+        if let Some(comment) = &node.comment {
+            output.push(' ');
+            output.push_str(tree.text(comment).trim_end());
+        }
+        output.push_str(&line[line.trim_end_matches(&['\r', '\n'][..]).len()..]);
+    }
+}
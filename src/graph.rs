@@ -0,0 +1,91 @@
+//! Graph of the files including each other, exported as Graphviz DOT or JSON.
+
+use std::path::{Path, PathBuf};
+
+/// `@include` directive seen while preprocessing.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IncludeEdge {
+    pub from: PathBuf,
+    /// Included file. Includes in inactive branches are not looked up, the path is relative
+    /// to the including file and keeps the expansions of unknown definitions.
+    pub to: PathBuf,
+    #[cfg_attr(feature = "serde", serde(rename = "line"))]
+    pub line_no: usize,
+    /// Whether the include was in an active branch, and the file was thus included.
+    pub active: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IncludeGraph {
+    files: Vec<PathBuf>,
+    edges: Vec<IncludeEdge>,
+}
+
+impl IncludeGraph {
+    /// Edges in the order the directives were seen, an include repeated at the same place
+    /// appears once.
+    pub fn edges(&self) -> &[IncludeEdge] {
+        &self.edges
+    }
+
+    /// The root and every file at an end of an edge, in order of appearance.
+    pub fn files(&self) -> Vec<&Path> {
+        self.files.iter().map(PathBuf::as_path).collect()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph includes {\n");
+        for file in self.files() {
+            dot.push_str(&format!("  {};\n", dot_string(file)));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!(
+                "  {} -> {} [label=\"{}\"{}];\n",
+                dot_string(&edge.from),
+                dot_string(&edge.to),
+                edge.line_no,
+                if edge.active { "" } else { ", style=dashed" }
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// `{"files": [path, ...], "edges": [{"from": path, "to": path, "line": n, "active": b}]}`
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::to_string(self).unwrap();
+        json.push('\n');
+        json
+    }
+
+    pub(crate) fn set_root(&mut self, root: &Path) {
+        if !self.files.iter().any(|file| file == root) {
+            self.files.insert(0, root.to_path_buf());
+        }
+    }
+
+    pub(crate) fn add_edge(&mut self, edge: IncludeEdge) {
+        if !self.edges.contains(&edge) {
+            for file in &[&edge.from, &edge.to] {
+                if !self.files.contains(file) {
+                    self.files.push(file.to_path_buf());
+                }
+            }
+            self.edges.push(edge);
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &IncludeGraph) {
+        for edge in &other.edges {
+            self.add_edge(edge.clone());
+        }
+    }
+}
+
+fn dot_string(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
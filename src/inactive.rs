@@ -0,0 +1,68 @@
+//! Source lines suppressed by conditionals.
+
+use std::path::{Path, PathBuf};
+
+/// Consecutive lines of a file in a branch which is not taken. Directives ending the branch
+/// are active, those nested in it are part of the region.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InactiveRegion {
+    pub path: PathBuf,
+    pub start_line_no: usize,
+    /// Last line of the region, included.
+    pub end_line_no: usize,
+    /// Line of the directive starting the outermost branch not taken, in the same file.
+    pub conditional_line_no: usize,
+}
+
+/// Inactive regions of the processed files, in the order they were read. A region read
+/// again when a file is included more than once appears once.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct InactiveRegions {
+    regions: Vec<InactiveRegion>,
+}
+
+impl InactiveRegions {
+    pub fn regions(&self) -> &[InactiveRegion] {
+        &self.regions
+    }
+
+    /// Regions of the file at `path`, in the order of their lines.
+    pub fn in_file(&self, path: &Path) -> Vec<&InactiveRegion> {
+        let mut regions: Vec<_> = self
+            .regions
+            .iter()
+            .filter(|region| region.path == path)
+            .collect();
+        regions.sort_by_key(|region| region.start_line_no);
+        regions
+    }
+
+    /// Adds a suppressed line, extending the last region if the line follows it.
+    pub(crate) fn add_line(&mut self, path: &Path, line_no: usize, conditional_line_no: usize) {
+        if let Some(last) = self.regions.last_mut() {
+            if last.path == path
+                && last.end_line_no + 1 == line_no
+                && last.conditional_line_no == conditional_line_no
+            {
+                last.end_line_no = line_no;
+                return;
+            }
+        }
+        self.regions.push(InactiveRegion {
+            path: path.to_path_buf(),
+            start_line_no: line_no,
+            end_line_no: line_no,
+            conditional_line_no,
+        });
+    }
+
+    pub(crate) fn merge(&mut self, other: &InactiveRegions) {
+        for region in &other.regions {
+            if !self.regions.contains(region) {
+                self.regions.push(region.clone());
+            }
+        }
+    }
+}
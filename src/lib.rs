@@ -1,56 +1,101 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use log::trace;
 use regex::Regex;
 
+pub mod analysis;
+pub mod batch;
 pub mod boolean_expression;
+pub mod cache;
 mod conditional_helper;
+pub mod dependencies;
+pub mod diagnostic;
+mod directive;
 pub mod errors;
+pub mod escape;
+pub mod explain;
+pub mod format;
+pub mod graph;
+pub mod inactive;
 pub mod location;
+#[cfg(feature = "serde")]
+pub mod sarif;
+pub mod specialize;
+pub mod syntax;
 
-use boolean_expression::parse_boolean_expression;
+use boolean_expression::{Context, Expr, Functions, Value};
+use cache::{IncludeCache, IncludeOutput, OutputKey};
 use conditional_helper::ConditionalHelper;
-use errors::{PreprocessorError, Result};
-use location::Location;
+use dependencies::Dependencies;
+use diagnostic::Diagnostic;
+use directive::{directive_text, misplaced_directive, Directive};
+use errors::{ErrorKind, PreprocessorError, Result};
+use escape::unescape;
+use explain::{Branch, Conditional, Explanation};
+use graph::{IncludeEdge, IncludeGraph};
+use inactive::InactiveRegions;
+use location::{IncludeSite, Location};
+
+/// Items exposed for the benchmarks only, which are not part of the API.
+#[cfg(feature = "internals")]
+#[doc(hidden)]
+pub mod internals {
+    pub use crate::directive::Directive;
+}
 
 pub type Definitions = HashMap<String, String>;
 
 lazy_static::lazy_static! {
-    static ref EXPANSION_RE: Regex = Regex::new(r"\$\(([0-9A-Z_a-z]+)\)").unwrap();
-    static ref INCLUDE_RE: Regex = Regex::new(r#"^\s*@include\s+"(.*)"\s*$"#).unwrap();
-    static ref DEFINE1_RE: Regex = Regex::new(r#"^\s*@define\s+([0-9A-Z_a-z]+)\s+"(.*)"\s*$"#).unwrap();
-    static ref DEFINE2_RE: Regex = Regex::new(r"^\s*@define\s+([0-9A-Z_a-z]+)\s+(\S+)\s*$").unwrap();
-    static ref DEFINE3_RE: Regex = Regex::new(r"^\s*@define\s+([0-9A-Z_a-z]+)\s*$").unwrap();
-    static ref UNDEF_RE: Regex = Regex::new(r"^\s*@undef\s+([0-9A-Z_a-z]+)\s*$").unwrap();
-    static ref IFDEF_RE: Regex = Regex::new(r"^\s*@ifdef\s+([0-9A-Z_a-z]+)\s*$").unwrap();
-    static ref IFNDEF_RE: Regex = Regex::new(r"^\s*@ifndef\s+([0-9A-Z_a-z]+)\s*$").unwrap();
-    static ref IF_RE: Regex = Regex::new(r"^\s*@if\s+(.*)").unwrap();
-    static ref ELIF_RE: Regex = Regex::new(r"^\s*@elif\s+(.*)").unwrap();
-    static ref ENDIF_RE: Regex = Regex::new(r"^\s*@endif\s*$").unwrap();
-    static ref ELSE_RE: Regex = Regex::new(r"^\s*@else\s*$").unwrap();
-    static ref FULL_LINE_COMMENT_RE: Regex = Regex::new(r"^\s*#.*").unwrap();
-    static ref COMMENT_RE: Regex = Regex::new(r"#.*").unwrap();
+    pub(crate) static ref EXPANSION_RE: Regex = Regex::new(r"\$\(([0-9A-Z_a-z]+)\)").unwrap();
 }
 
 #[derive(Debug, Default)]
-pub struct SleighPreprocessor<'a> {
+pub struct SleighPreprocessor {
     definitions: Option<Definitions>,
     locations: Option<Vec<Location>>,
     compatible: bool,
+    indented_directives: bool,
+    include_dirs: Vec<PathBuf>,
+    functions: Functions,
+    custom_functions: bool,
+    diagnostics: Vec<Diagnostic>,
+    dependencies: Dependencies,
+    include_graph: IncludeGraph,
+    inactive_regions: InactiveRegions,
+    cache: Option<IncludeCache>,
+    /// Contents of the root file if it is not read from `file_path`.
+    source: Option<String>,
+    consultation: Consultation,
+    /// Line to explain, see [`SleighPreprocessor::with_explain`].
+    explain: Option<(PathBuf, usize)>,
+    explanations: Vec<Explanation>,
 
     ifstack: Vec<ConditionalHelper>,
+    /// Conditionals enclosing the current line, recorded when a line is explained.
+    conditionals: Vec<Conditional>,
+    /// Line of the current file to explain.
+    explained_line: Option<usize>,
     error_count: u64,
 
     file_path: PathBuf,
-    line: Option<&'a str>,
+    /// Includes through which the file is included, the one in the root first.
+    includes: Vec<IncludeSite>,
     line_no: usize,
     overall_line_no: usize,
+    /// Line number in the output of the next line written, shared with included files.
+    output_line_no: usize,
+}
+
+/// Definitions looked at by a file and its includes before changing them, and those changed.
+#[derive(Debug, Default)]
+struct Consultation {
+    consulted: HashMap<String, Option<String>>,
+    written: HashSet<String>,
 }
 
-impl<'a> SleighPreprocessor<'a> {
+impl SleighPreprocessor {
     pub fn new<P>(definitions: Definitions, file_path: P, is_compatible: bool) -> Self
     where
         P: Into<PathBuf>,
@@ -60,11 +105,74 @@ impl<'a> SleighPreprocessor<'a> {
             locations: Some(Vec::new()),
             file_path: file_path.into(),
             compatible: is_compatible,
+            indented_directives: true,
             ..Default::default()
         }
     }
 
+    /// Whether directives may be indented, which is the default. Ghidra only recognizes
+    /// directives at the start of a line and copies indented ones as text.
+    pub fn with_indented_directives(mut self, indented_directives: bool) -> Self {
+        self.indented_directives = indented_directives;
+        self
+    }
+
+    /// Preprocesses `source` instead of reading the root file. The file path still names the
+    /// root in markers, locations and errors, and included files are looked up next to it.
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Adds a directory in which included files are looked up when they are not found
+    /// relative to the including file, after the directories added before.
+    pub fn with_include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Makes `function` callable from `@if`/`@elif` expressions under `name`, in addition to
+    /// the built-in functions.
+    pub fn with_function<S, F>(mut self, name: S, function: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&[Value], &Context) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions.register(name, function);
+        self.custom_functions = true;
+        self
+    }
+
+    /// Records why the line `line_no` of `path` is copied or not each time it is read, with
+    /// the conditionals enclosing it.
+    pub fn with_explain(mut self, path: impl Into<PathBuf>, line_no: usize) -> Self {
+        self.explain = Some((path.into(), line_no));
+        self
+    }
+
+    /// Reads files through `cache` and reuses the output of includes it holds.
+    ///
+    /// Outputs are neither stored nor reused when custom functions are registered, as they
+    /// may look at any definition, nor when a line is explained, as it has to be read.
+    pub fn with_include_cache(mut self, cache: IncludeCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Preprocesses the root file, appending the output to `writer`. Running it again after it
+    /// succeeded starts from the definitions it left, the other results are replaced.
     pub fn process(&mut self, writer: &mut String) -> Result<()> {
+        self.locations = Some(Vec::new());
+        self.diagnostics.clear();
+        self.dependencies = Dependencies::default();
+        self.include_graph = IncludeGraph::default();
+        self.inactive_regions = InactiveRegions::default();
+        self.explanations.clear();
+        self.consultation = Consultation::default();
+        self.ifstack.clear();
+        self.conditionals.clear();
+        self.output_line_no = writer.matches('\n').count() + 1;
+        self.include_graph.set_root(&self.file_path);
         let (definitions, locations) = self.process_internal(writer, 1)?;
         self.definitions = Some(definitions);
         self.locations = Some(locations);
@@ -87,27 +195,247 @@ impl<'a> SleighPreprocessor<'a> {
         self.locations.take().unwrap()
     }
 
+    /// Warnings about the processed files, in the order of the lines they refer to.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Files read and included files looked up in vain, including those of a failed run.
+    pub fn dependencies(&self) -> &Dependencies {
+        &self.dependencies
+    }
+
+    /// Lines suppressed by conditionals in the processed files, including those of a failed
+    /// run.
+    pub fn inactive_regions(&self) -> &InactiveRegions {
+        &self.inactive_regions
+    }
+
+    /// Explanations of the line given to [`with_explain`](Self::with_explain), one per time
+    /// it was read, including those of a failed run.
+    pub fn explanations(&self) -> &[Explanation] {
+        &self.explanations
+    }
+
+    /// Includes seen in the processed files, including those of a failed run.
+    pub fn include_graph(&self) -> &IncludeGraph {
+        &self.include_graph
+    }
+
     fn include_file(
         &mut self,
         writer: &mut String,
         overall_line_no: usize,
         file_path: impl Into<PathBuf>,
     ) -> Result<()> {
+        let file_path = file_path.into();
+        let cache = self
+            .cache
+            .clone()
+            .filter(|_| !self.custom_functions && self.explain.is_none());
+        let key = OutputKey {
+            path: file_path.clone(),
+            compatible: self.compatible,
+            indented_directives: self.indented_directives,
+            include_dirs: self.include_dirs.clone(),
+        };
+        if let Some(output) = cache
+            .as_ref()
+            .and_then(|cache| cache.output(&key, self.definitions.as_ref().unwrap()))
+        {
+            trace!("reusing output of {}", file_path.display());
+            self.reuse_output(writer, overall_line_no, &output);
+            return Ok(());
+        }
+
+        let text_start = writer.len();
+        let locations_start = self.locations().len();
+        let diagnostics_start = self.diagnostics.len();
+        let output_line_no = self.output_line_no;
         let definitions = self.definitions.take();
         let locations = self.locations.take();
+        let mut includes = self.includes.clone();
+        includes.push(IncludeSite {
+            path: self.file_path.clone(),
+            line_no: self.line_no,
+        });
         let mut preprocessor = SleighPreprocessor {
             compatible: self.compatible,
-            file_path: file_path.into(),
+            indented_directives: self.indented_directives,
+            include_dirs: self.include_dirs.clone(),
+            functions: self.functions.clone(),
+            custom_functions: self.custom_functions,
+            diagnostics: std::mem::take(&mut self.diagnostics),
+            cache: self.cache.clone(),
+            explain: self.explain.clone(),
+            explanations: std::mem::take(&mut self.explanations),
+            conditionals: self.conditionals.clone(),
+            file_path,
+            includes,
+            output_line_no: self.output_line_no,
             definitions,
             locations,
             ..Default::default()
         };
-        let (definitions, locations) = preprocessor.process_internal(writer, overall_line_no)?;
+        let result = preprocessor.process_internal(writer, overall_line_no);
+        self.diagnostics = preprocessor.diagnostics;
+        self.explanations = preprocessor.explanations;
+        self.dependencies.merge(&preprocessor.dependencies);
+        self.include_graph.merge(&preprocessor.include_graph);
+        self.inactive_regions.merge(&preprocessor.inactive_regions);
+        self.output_line_no = preprocessor.output_line_no;
+        let (definitions, locations) = result?;
         self.definitions = Some(definitions);
         self.locations = Some(locations);
+
+        if let Some(cache) = cache {
+            let definitions = self.definitions();
+            let consultation = preprocessor.consultation;
+            let output = IncludeOutput {
+                consulted: consultation.consulted.into_iter().collect(),
+                written: consultation
+                    .written
+                    .into_iter()
+                    .map(|name| {
+                        let value = definitions.get(&name).cloned();
+                        (name, value)
+                    })
+                    .collect(),
+                text: writer[text_start..].to_string(),
+                locations: self.locations()[locations_start..]
+                    .iter()
+                    .map(|l| {
+                        let global_line_num = l.global_line_num() - output_line_no;
+                        Location::new(l.filepath(), l.local_line_num(), global_line_num)
+                    })
+                    .collect(),
+                diagnostics: self.diagnostics[diagnostics_start..]
+                    .iter()
+                    .map(|d| {
+                        let overall_line_no = d.overall_line_no() - overall_line_no;
+                        let includes = d.includes()[self.includes.len() + 1..].to_vec();
+                        d.clone()
+                            .with_overall_line_no(overall_line_no)
+                            .with_includes(includes)
+                    })
+                    .collect(),
+                dependencies: preprocessor.dependencies,
+                include_graph: preprocessor.include_graph,
+                inactive_regions: preprocessor.inactive_regions,
+            };
+            self.absorb(&output);
+            cache.insert_output(key, output);
+        }
         Ok(())
     }
 
+    /// Does what including a file did before, as if it was included again.
+    fn reuse_output(
+        &mut self,
+        writer: &mut String,
+        overall_line_no: usize,
+        output: &IncludeOutput,
+    ) {
+        let output_line_no = self.output_line_no;
+        self.locations
+            .as_mut()
+            .unwrap()
+            .extend(output.locations.iter().map(|l| {
+                let global_line_num = output_line_no + l.global_line_num();
+                Location::new(l.filepath(), l.local_line_num(), global_line_num)
+            }));
+        let site = IncludeSite {
+            path: self.file_path.clone(),
+            line_no: self.line_no,
+        };
+        let includes = &self.includes;
+        self.diagnostics.extend(output.diagnostics.iter().map(|d| {
+            let overall_line_no = overall_line_no + d.overall_line_no();
+            let includes = includes
+                .iter()
+                .chain(std::iter::once(&site))
+                .chain(d.includes())
+                .cloned()
+                .collect();
+            d.clone()
+                .with_overall_line_no(overall_line_no)
+                .with_includes(includes)
+        }));
+        self.write(writer, &output.text);
+        self.dependencies.merge(&output.dependencies);
+        self.include_graph.merge(&output.include_graph);
+        self.inactive_regions.merge(&output.inactive_regions);
+        let definitions = self.definitions.as_mut().unwrap();
+        for (name, value) in &output.written {
+            match value {
+                Some(value) => definitions.insert(name.clone(), value.clone()),
+                None => definitions.remove(name),
+            };
+        }
+        self.absorb(output);
+    }
+
+    /// Adds what an include consulted and changed to what this file did.
+    fn absorb(&mut self, output: &IncludeOutput) {
+        for (name, value) in &output.consulted {
+            if !self.consultation.written.contains(name) {
+                self.consultation
+                    .consulted
+                    .entry(name.clone())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+        self.consultation
+            .written
+            .extend(output.written.iter().map(|(name, _)| name.clone()));
+    }
+
+    /// Records that the value of a definition is looked at.
+    fn consult(&mut self, name: &str) {
+        if self.cache.is_none() || self.consultation.written.contains(name) {
+            return;
+        }
+        if !self.consultation.consulted.contains_key(name) {
+            let value = self.definitions().get(name).cloned();
+            self.consultation.consulted.insert(name.to_string(), value);
+        }
+    }
+
+    /// Records that a definition is defined or undefined.
+    fn change(&mut self, name: &str) {
+        if self.cache.is_some() {
+            self.consultation.written.insert(name.to_string());
+        }
+    }
+
+    /// Path of an included file: relative paths are looked up next to the including file,
+    /// then in the include directories. The path next to the including file is returned if
+    /// the file is found nowhere.
+    fn resolve_include(&mut self, path: impl Into<PathBuf>) -> PathBuf {
+        let path = path.into();
+        let local = self.directory().join(&path);
+        let candidates = std::iter::once(local.clone())
+            .chain(self.include_dirs.iter().map(|dir| dir.join(&path)))
+            .take(if path.is_absolute() { 1 } else { usize::MAX });
+        for candidate in candidates {
+            if candidate.exists() {
+                return candidate;
+            }
+            self.dependencies.add_missing(&candidate);
+        }
+        local
+    }
+
+    fn read_lines(&mut self) -> std::io::Result<Arc<Vec<String>>> {
+        let lines = match (&self.source, &self.cache) {
+            (Some(source), _) => Arc::new(source.lines().map(String::from).collect()),
+            (None, Some(cache)) => cache.lines(&self.file_path)?,
+            (None, None) => Arc::new(cache::read_lines(&self.file_path)?),
+        };
+        self.dependencies.add_file(&self.file_path);
+        Ok(lines)
+    }
+
     fn process_internal(
         &mut self,
         writer: &mut String,
@@ -118,141 +446,230 @@ impl<'a> SleighPreprocessor<'a> {
         self.ifstack
             .push(ConditionalHelper::new(false, false, false, true));
 
-        let file = File::open(&self.file_path)?;
-        let reader = BufReader::new(file);
-        self.output_position(writer, writer.lines().count() + 1);
+        self.explained_line = match &self.explain {
+            Some((path, line_no)) if same_file(path, &self.file_path) => Some(*line_no),
+            _ => None,
+        };
+        let lines = self.read_lines()?;
+        self.output_position(writer);
         trace!("enter SleighPreprocessor");
+        self.process_lines(writer, &lines)
+            .map_err(|e| self.locate_error(e, &lines))
+    }
 
-        for line in reader.lines() {
-            let mut line: String = line?;
+    fn process_lines(
+        &mut self,
+        writer: &mut String,
+        lines: &[String],
+    ) -> Result<(Definitions, Vec<Location>)> {
+        for original_line in lines {
+            let original_line = original_line.as_str();
             trace!("top of while, state: {:?}", self);
-            trace!("got line: {}", line);
-
-            let original_line = line.clone();
+            trace!("got line: {}", original_line);
+
+            // whether the line is an `@if*`, `@elif` or `@else` directive
+            let mut starts_branch = false;
+
+            if self.explained_line == Some(self.line_no) {
+                self.explanations.push(Explanation {
+                    path: self.file_path.clone(),
+                    line_no: self.line_no,
+                    includes: self.includes.clone(),
+                    active: self.is_copy(),
+                    conditionals: self.conditionals.clone(),
+                });
+            }
 
             // remove confirmed full-line comments
-            line = FULL_LINE_COMMENT_RE.replace(&line, "").to_string();
-
-            if !line.is_empty() && line.starts_with('@') {
-                // remove any comments in preprocessor
-                line = COMMENT_RE.replace(&line, "").to_string();
-
-                if let Some(m) = INCLUDE_RE.captures(&line) {
-                    if self.is_copy() {
-                        let mut include_file_path =
-                            PathBuf::from(self.handle_variables(m.get(1).unwrap().as_str(), true)?);
-                        if include_file_path.is_relative() {
-                            include_file_path = PathBuf::from(&self.file_path)
-                                .parent()
-                                .unwrap()
-                                .join(include_file_path);
+            let line = if directive::is_full_line_comment(original_line) {
+                ""
+            } else {
+                original_line
+            };
+
+            if let Some((kind, message)) = misplaced_directive(line, self.indented_directives) {
+                let diagnostic = Diagnostic::warning(
+                    kind,
+                    message.to_string(),
+                    self.file_path.clone(),
+                    self.line_no,
+                    self.overall_line_no,
+                    line.to_string(),
+                );
+                self.diagnostics
+                    .push(diagnostic.with_includes(self.includes.clone()));
+            }
+
+            if let Some(line) = directive_text(line, self.indented_directives) {
+                match Directive::parse(line) {
+                    Some(Directive::Include(path)) => {
+                        if self.is_copy() {
+                            let path = self.handle_variables(path, true)?;
+                            let include_file_path = self.resolve_include(path);
+                            if !include_file_path.exists() {
+                                return Err(PreprocessorError::new(
+                                    ErrorKind::MissingInclude,
+                                    format!(
+                                        "included file \"{}\" does not exist",
+                                        include_file_path.display()
+                                    ),
+                                    self.file_name(),
+                                    self.line_no,
+                                    self.overall_line_no,
+                                    line.to_string(),
+                                )
+                                .into());
+                            }
+                            self.include_graph.add_edge(IncludeEdge {
+                                from: self.file_path.clone(),
+                                to: include_file_path.clone(),
+                                line_no: self.line_no,
+                                active: true,
+                            });
+                            self.include_file(writer, self.overall_line_no, include_file_path)?;
+                            // increment the position now because we already replaced the include
+                            self.line_no += 1;
+                            self.overall_line_no += 1;
+                            self.output_position(writer);
+                            // the one directive we skip printing a blank line
+                            continue;
+                        } else {
+                            let path = self.expand_known(path);
+                            self.include_graph.add_edge(IncludeEdge {
+                                from: self.file_path.clone(),
+                                to: self.directory().join(path),
+                                line_no: self.line_no,
+                                active: false,
+                            });
                         }
-                        if !include_file_path.exists() {
-                            return Err(PreprocessorError::new(
-                                format!(
-                                    "included file \"{}\" does not exist",
-                                    include_file_path.display()
-                                ),
-                                self.file_name(),
-                                self.line_no,
-                                self.overall_line_no,
-                                line,
-                            )
-                            .into());
+                    }
+                    Some(Directive::Define {
+                        name,
+                        value,
+                        quoted,
+                    }) => {
+                        if self.is_copy() {
+                            let value = match value {
+                                Some(value) if quoted => unescape(value).map_err(|e| {
+                                    PreprocessorError::new(
+                                        ErrorKind::InvalidEscape,
+                                        format!("{}", e),
+                                        self.file_name(),
+                                        self.line_no,
+                                        self.overall_line_no,
+                                        line.to_string(),
+                                    )
+                                })?,
+                                value => value.unwrap_or_default().to_string(),
+                            };
+                            self.define(name.to_string(), value);
+                        }
+                    }
+                    Some(Directive::Undef(name)) => {
+                        if self.is_copy() {
+                            self.undefine(name);
                         }
-                        self.include_file(writer, self.overall_line_no, include_file_path)?;
-                        // increment the position now because we already replaced the include
-                        self.line_no += 1;
-                        self.overall_line_no += 1;
-                        self.output_position(writer, writer.lines().count() + 1);
-                        // the one directive we skip printing a blank line
-                        continue;
                     }
-                } else if let Some(m) = DEFINE1_RE
-                    .captures(&line)
-                    .or_else(|| DEFINE2_RE.captures(&line))
-                {
-                    if self.is_copy() {
-                        let key = m.get(1).unwrap().as_str();
-                        let value = m.get(2).unwrap().as_str();
-                        self.define(key, value);
+                    Some(Directive::Ifdef(m)) => {
+                        self.enter_if();
+                        starts_branch = true;
+                        self.consult(m);
+                        if self.definitions.as_ref().unwrap().contains_key(m) {
+                            self.set_handled(true);
+                            trace!("@ifdef {}: yes", m);
+                        } else {
+                            self.set_copy(false);
+                            trace!("@ifdef {}: NO", m);
+                        }
+                        self.explain_branch(true, original_line, Some(self.is_handled()), m);
                     }
-                } else if let Some(m) = DEFINE3_RE.captures(&line) {
-                    if self.is_copy() {
-                        self.define(m.get(1).unwrap().as_str(), "");
+                    Some(Directive::Ifndef(m)) => {
+                        self.enter_if();
+                        starts_branch = true;
+                        self.consult(m);
+                        if self.definitions.as_ref().unwrap().contains_key(m) {
+                            self.set_copy(false);
+                            trace!("@ifndef {}: NO", m);
+                        } else {
+                            self.set_handled(true);
+                            trace!("@ifndef {}: yes", m);
+                        }
+                        self.explain_branch(true, original_line, Some(self.is_handled()), m);
                     }
-                } else if let Some(m) = UNDEF_RE.captures(&line) {
-                    if self.is_copy() {
-                        self.undefine(m.get(1).unwrap().as_str());
+                    Some(Directive::If(m)) => {
+                        self.enter_if();
+                        starts_branch = true;
+                        trace!("@if... {}", m);
+                        let value = self.handle_expression(m)?;
+                        self.explain_branch(true, original_line, value, m);
                     }
-                } else if let Some(m) = IFDEF_RE.captures(&line) {
-                    self.enter_if();
-                    let m = m.get(1).unwrap().as_str();
-                    if self.definitions.as_ref().unwrap().contains_key(m) {
-                        self.set_handled(true);
-                        trace!("@ifdef {}: yes", m);
-                    } else {
-                        self.set_copy(false);
-                        trace!("@ifdef {}: NO", m);
+                    Some(Directive::Elif(m)) => {
+                        self.enter_elif(line)?;
+                        starts_branch = true;
+                        trace!("@elif... {}", m);
+                        let value = self.handle_expression(m)?;
+                        self.explain_branch(false, original_line, value, m);
                     }
-                } else if let Some(m) = IFNDEF_RE.captures(&line) {
-                    self.enter_if();
-                    let m = m.get(1).unwrap().as_str();
-                    if self.definitions.as_ref().unwrap().contains_key(m) {
-                        self.set_copy(false);
-                        trace!("@ifndef {}: NO", m);
-                    } else {
-                        self.set_handled(true);
-                        trace!("@ifndef {}: yes", m);
+                    Some(Directive::Endif) => {
+                        self.leave_if(line)?;
+                        trace!("@endif");
+                    }
+                    Some(Directive::Else) => {
+                        self.enter_else(line)?;
+                        starts_branch = true;
+                        self.set_copy(!self.is_handled());
+                        trace!("@else");
+                        self.explain_branch(false, original_line, None, "");
+                    }
+                    None => {
+                        return Err(PreprocessorError::new(
+                            ErrorKind::UnrecognizedDirective,
+                            "unrecognized preprocessor directive",
+                            self.file_name(),
+                            self.line_no,
+                            self.overall_line_no,
+                            line,
+                        )
+                        .into());
                     }
-                } else if let Some(m) = IF_RE.captures(&line) {
-                    self.enter_if();
-                    let m = m.get(1).unwrap().as_str();
-                    trace!("@if... {}", m);
-                    self.handle_expression(m)?;
-                } else if let Some(m) = ELIF_RE.captures(&line) {
-                    self.enter_elif(&line)?;
-                    let m = m.get(1).unwrap().as_str();
-                    trace!("@elif... {}", m);
-                    self.handle_expression(m)?;
-                } else if ENDIF_RE.is_match(&line) {
-                    self.leave_if(&line)?;
-                    trace!("@endif");
-                } else if ELSE_RE.is_match(&line) {
-                    self.enter_else(line)?;
-                    self.set_copy(!self.is_handled());
-                    trace!("@else");
-                } else {
-                    return Err(PreprocessorError::new(
-                        "unrecognized preprocessor directive",
-                        self.file_name(),
-                        self.line_no,
-                        self.overall_line_no,
-                        &line,
-                    )
-                    .into());
                 }
                 trace!(
                     "PRINT {}: commenting directive out",
                     self.current_position()
                 );
-                writer.push_str(&format!("#{}\n", original_line));
+                self.write(writer, &format!("#{}\n", original_line));
             } else if self.is_copy() {
                 trace!("PRINT {}: printing text", self.current_position());
-                writer.push_str(&self.handle_variables(&line, self.compatible)?);
-                writer.push('\n');
+                let text = self.handle_variables(line, self.compatible)?;
+                self.write(writer, &text);
+                self.write(writer, "\n");
             } else {
                 trace!(
                     "PRINT {}: replacing text with non-copied blank line",
                     self.current_position()
                 );
-                writer.push_str(&format!("#{}\n", &line));
+                self.write(writer, &format!("#{}\n", line));
+            }
+            // a directive starting a branch ends the previous one, it is active unless an
+            // enclosing conditional is not copied
+            let enclosing = if starts_branch {
+                self.ifstack.len() - 1
+            } else {
+                self.ifstack.len()
+            };
+            if let Some(conditional) = self.ifstack[..enclosing].iter().find(|c| !c.copy()) {
+                self.inactive_regions.add_line(
+                    &self.file_path,
+                    self.line_no,
+                    conditional.line_no(),
+                );
             }
             self.line_no += 1;
             self.overall_line_no += 1;
         }
         if self.error_count > 0 {
             return Err(PreprocessorError::new(
+                ErrorKind::Preprocessor,
                 "Error during preprocessing",
                 self.file_name(),
                 self.overall_line_no,
@@ -268,6 +685,22 @@ impl<'a> SleighPreprocessor<'a> {
         ))
     }
 
+    /// Completes the position of an error raised in this file.
+    fn locate_error(&self, error: errors::Error, lines: &[String]) -> errors::Error {
+        match error {
+            errors::Error::Preprocessor(mut e) => {
+                let source_line = e.line_no().checked_sub(1).and_then(|i| lines.get(i));
+                e.locate(
+                    &self.file_path,
+                    &self.includes,
+                    source_line.map(String::as_str),
+                );
+                e.into()
+            }
+            e => e,
+        }
+    }
+
     fn current_position(&self) -> String {
         format!(
             "{}:{}({})",
@@ -277,17 +710,31 @@ impl<'a> SleighPreprocessor<'a> {
         )
     }
 
-    fn output_position(&mut self, writer: &mut String, all_line_count: usize) {
+    fn output_position(&mut self, writer: &mut String) {
         let file_name = self.file_name().to_string();
         let line_no = self.line_no;
+        let output_line_no = self.output_line_no;
         if !self.compatible {
             let position = format!("\x08{}###{}\x08", file_name, line_no);
-            writer.push_str(&position);
+            self.write(writer, &position);
         }
-        self.locations
-            .as_mut()
-            .unwrap()
-            .push(Location::new(&self.file_path, line_no, all_line_count));
+        self.locations.as_mut().unwrap().push(Location::new(
+            &self.file_path,
+            line_no,
+            output_line_no,
+        ));
+    }
+
+    /// Appends `text` to the output, keeping count of its lines.
+    fn write(&mut self, writer: &mut String, text: &str) {
+        self.output_line_no += text.matches('\n').count();
+        writer.push_str(text);
+    }
+
+    /// Directory of the file, empty for a root without one such as `/`, so that includes
+    /// are looked up in the working directory.
+    fn directory(&self) -> &Path {
+        self.file_path.parent().unwrap_or_else(|| Path::new(""))
     }
 
     fn file_name(&self) -> &str {
@@ -297,12 +744,16 @@ impl<'a> SleighPreprocessor<'a> {
             .unwrap_or("")
     }
 
-    fn handle_expression<S: AsRef<str>>(&mut self, expression: S) -> Result<()> {
+    /// Value of the expression, if it is evaluated.
+    fn handle_expression<S: AsRef<str>>(&mut self, expression: S) -> Result<Option<bool>> {
         let expression = expression.as_ref();
         if self.is_handled() {
             self.set_copy(false);
             trace!("already handled");
-        } else if !self.parse_expression(expression)? {
+            return Ok(None);
+        }
+        let value = self.parse_expression(expression)?;
+        if !value {
             self.set_copy(false);
             trace!("expression \"{}\" is FALSE", expression);
         } else {
@@ -310,13 +761,58 @@ impl<'a> SleighPreprocessor<'a> {
             self.set_handled(true);
             trace!("expression \"{}\" is true", expression);
         }
-        Ok(())
+        Ok(Some(value))
     }
 
-    fn parse_expression<S: AsRef<str>>(&self, expression: S) -> Result<bool> {
+    /// Records the branch a conditional directive starts, if a line is explained.
+    fn explain_branch(
+        &mut self,
+        is_first: bool,
+        directive: &str,
+        value: Option<bool>,
+        condition: &str,
+    ) {
+        if self.explain.is_none() {
+            return;
+        }
+        let definitions = match Expr::parse(condition) {
+            Ok(expr) => expr
+                .referenced_identifiers()
+                .into_iter()
+                .map(|name| (name.to_string(), self.definitions().get(name).cloned()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let branch = Branch {
+            line_no: self.line_no,
+            directive: directive.trim().to_string(),
+            value,
+            taken: self.ifstack.last().unwrap().copy(),
+            definitions,
+        };
+        if is_first {
+            self.conditionals.push(Conditional {
+                path: self.file_path.clone(),
+                branches: vec![branch],
+            });
+        } else if let Some(conditional) = self.conditionals.last_mut() {
+            conditional.branches.push(branch);
+        }
+    }
+
+    fn parse_expression<S: AsRef<str>>(&mut self, expression: S) -> Result<bool> {
         let expression = expression.as_ref();
-        parse_boolean_expression(expression, &self.definitions.as_ref().unwrap()).map_err(|e| {
+        let result = Expr::parse(expression).and_then(|expr| {
+            for name in expr.referenced_identifiers() {
+                self.consult(name);
+            }
+            let context = Context::new(self.definitions.as_ref().unwrap(), &self.functions)
+                .with_directory(Some(self.directory()));
+            expr.eval_with(&context)
+        });
+        result.map_err(|e| {
             PreprocessorError::new(
+                e.kind(),
                 format!("parser error: {}", e),
                 self.file_name(),
                 self.line_no,
@@ -327,7 +823,11 @@ impl<'a> SleighPreprocessor<'a> {
         })
     }
 
-    fn handle_variables<S: Into<String>>(&self, input: S, is_compatible: bool) -> Result<String> {
+    fn handle_variables<S: Into<String>>(
+        &mut self,
+        input: S,
+        is_compatible: bool,
+    ) -> Result<String> {
         let mut input = input.into();
         let mut output = String::new();
         while let Some(m) = EXPANSION_RE.captures(&input) {
@@ -336,6 +836,7 @@ impl<'a> SleighPreprocessor<'a> {
             let expansion = expansion_match.as_str();
             trace!("found expansion: {}", expansion);
             let variable = m.get(1).unwrap().as_str();
+            self.consult(variable);
             let definiton = self
                 .definitions
                 .as_ref()
@@ -343,6 +844,7 @@ impl<'a> SleighPreprocessor<'a> {
                 .get(variable)
                 .ok_or_else(|| {
                     errors::Error::Preprocessor(PreprocessorError::new(
+                        ErrorKind::NotDefined,
                         format!("unknown variable: {}", variable),
                         self.file_name(),
                         self.line_no,
@@ -363,6 +865,24 @@ impl<'a> SleighPreprocessor<'a> {
         Ok(output)
     }
 
+    /// Expands the known definitions in `input`, leaving the others as they are.
+    fn expand_known(&mut self, input: &str) -> String {
+        let variables: Vec<_> = EXPANSION_RE
+            .captures_iter(input)
+            .map(|m| m[1].to_string())
+            .collect();
+        for variable in &variables {
+            self.consult(variable);
+        }
+        let definitions = self.definitions();
+        EXPANSION_RE
+            .replace_all(input, |m: &regex::Captures| match definitions.get(&m[1]) {
+                Some(value) => value.clone(),
+                None => m[0].to_string(),
+            })
+            .into_owned()
+    }
+
     fn define<S>(&mut self, key: S, value: S)
     where
         S: Into<String>,
@@ -370,6 +890,7 @@ impl<'a> SleighPreprocessor<'a> {
         let key = key.into();
         let value = value.into();
         trace!("@define {} {}", key, value);
+        self.change(&key);
         self.definitions.as_mut().unwrap().insert(key, value);
     }
 
@@ -379,17 +900,20 @@ impl<'a> SleighPreprocessor<'a> {
     {
         let key = key.into();
         trace!("@undef {}", key);
+        self.change(&key);
         self.definitions.as_mut().unwrap().remove(&key);
     }
 
     fn enter_if(&mut self) {
         self.ifstack
             .push(ConditionalHelper::new(true, false, false, self.is_copy()));
+        self.set_line_no(self.line_no);
     }
 
     fn enter_elif<S: AsRef<str>>(&mut self, line: S) -> Result<()> {
         if !self.is_in_if() {
             return Err(PreprocessorError::new(
+                ErrorKind::UnbalancedConditional,
                 "elif outside of IF* directive",
                 self.file_name(),
                 self.line_no,
@@ -400,6 +924,7 @@ impl<'a> SleighPreprocessor<'a> {
         }
         if self.is_saw_else() {
             return Err(PreprocessorError::new(
+                ErrorKind::UnbalancedConditional,
                 "already saw else directive",
                 self.file_name(),
                 self.line_no,
@@ -408,12 +933,14 @@ impl<'a> SleighPreprocessor<'a> {
             )
             .into());
         }
+        self.set_line_no(self.line_no);
         Ok(())
     }
 
     fn leave_if<S: AsRef<str>>(&mut self, line: S) -> Result<()> {
         if !self.is_in_if() {
             return Err(PreprocessorError::new(
+                ErrorKind::UnbalancedConditional,
                 "not in IF* directive",
                 self.file_name(),
                 self.line_no,
@@ -423,12 +950,14 @@ impl<'a> SleighPreprocessor<'a> {
             .into());
         }
         self.ifstack.pop();
+        self.conditionals.pop();
         Ok(())
     }
 
     fn enter_else<S: AsRef<str>>(&mut self, line: S) -> Result<()> {
         if !self.is_in_if() {
             return Err(PreprocessorError::new(
+                ErrorKind::UnbalancedConditional,
                 "else outside of IF* directive",
                 self.file_name(),
                 self.line_no,
@@ -439,6 +968,7 @@ impl<'a> SleighPreprocessor<'a> {
         }
         if self.is_saw_else() {
             return Err(PreprocessorError::new(
+                ErrorKind::UnbalancedConditional,
                 "duplicate else directive",
                 self.file_name(),
                 self.line_no,
@@ -448,6 +978,7 @@ impl<'a> SleighPreprocessor<'a> {
             .into());
         }
         self.set_saw_else(true);
+        self.set_line_no(self.line_no);
         Ok(())
     }
 
@@ -480,4 +1011,12 @@ impl<'a> SleighPreprocessor<'a> {
     fn is_handled(&self) -> bool {
         self.ifstack.last().unwrap().handled()
     }
+
+    fn set_line_no(&mut self, line_no: usize) {
+        self.ifstack.last_mut().unwrap().set_line_no(line_no);
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
 }
@@ -1,6 +1,7 @@
 use std::path::{Path, PathBuf};
 
 #[derive(Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Location {
     filepath: PathBuf,
     local_line_num: usize,
@@ -32,3 +33,11 @@ impl Location {
         &self.filepath
     }
 }
+
+/// `@include` directive through which a file was included.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IncludeSite {
+    pub path: PathBuf,
+    pub line_no: usize,
+}
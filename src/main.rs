@@ -1,23 +1,708 @@
-use std::collections::HashMap;
-use std::env::args;
-use std::fs::File;
-use std::io::Write;
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use sleigh_preprocessor::SleighPreprocessor;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use sleigh_preprocessor::analysis::check_conditionals;
+use sleigh_preprocessor::batch::{BatchPreprocessor, Root};
+use sleigh_preprocessor::boolean_expression::KnownDefinitions;
+use sleigh_preprocessor::diagnostic::Diagnostic;
+use sleigh_preprocessor::errors::{Error, Result};
+#[cfg(feature = "serde")]
+use sleigh_preprocessor::explain::Explanation;
+use sleigh_preprocessor::format::Formatter;
+#[cfg(feature = "serde")]
+use sleigh_preprocessor::inactive::InactiveRegions;
+#[cfg(feature = "serde")]
+use sleigh_preprocessor::location::Location;
+#[cfg(feature = "serde")]
+use sleigh_preprocessor::sarif::SarifLog;
+use sleigh_preprocessor::specialize::Specializer;
+use sleigh_preprocessor::{Definitions, SleighPreprocessor};
+
+const EXIT_STATUS: &str = "\
+Exit status: 0 on success, 1 if preprocessing fails, 2 on usage errors and 3 on I/O errors.";
+
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    after_help = EXIT_STATUS
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    preprocess: PreprocessArgs,
+}
+
+/// Options of preprocessing shared by a single file and a directory.
+#[derive(Args)]
+struct InputArgs {
+    /// Definition, a value is required as by `sleigh -D` but it may be empty
+    #[arg(short = 'D', value_name = "NAME=VALUE", value_parser = parse_definition)]
+    define: Vec<(String, String)>,
+    /// Removes a definition given with -D
+    #[arg(short = 'U', value_name = "NAME")]
+    undefine: Vec<String>,
+    /// Directory searched for included files which are not next to the including file
+    #[arg(short = 'I', value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+    /// Leave the origin of lines and expansions unmarked, as Ghidra's preprocessor does,
+    /// which is the default; indented directives are still processed
+    #[arg(long, overrides_with = "markers")]
+    compatible: bool,
+    /// Mark the origin of lines and expansions in the output
+    #[arg(long, overrides_with = "compatible")]
+    markers: bool,
+    /// Copy indented directives as text, as Ghidra's preprocessor does
+    #[arg(long)]
+    no_indented_directives: bool,
+}
+
+impl InputArgs {
+    fn definitions(&self) -> Definitions {
+        let mut definitions: Definitions = self.define.iter().cloned().collect();
+        for name in &self.undefine {
+            definitions.remove(name);
+        }
+        definitions
+    }
+}
+
+#[derive(Args)]
+struct PreprocessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Output file, standard output if it is `-` or not given
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Print the definitions left after preprocessing, to standard error if the output goes
+    /// to standard output
+    #[arg(long)]
+    print_defs: bool,
+    /// Print the output line at which each file starts or resumes, to standard error if the
+    /// output goes to standard output
+    #[arg(long)]
+    print_locations: bool,
+    /// Print the ranges of lines suppressed by conditionals, each followed by the line of the
+    /// directive starting the branch not taken, to standard error if the output goes to
+    /// standard output, like --print-defs
+    #[arg(long)]
+    print_inactive: bool,
+    /// Print the conditionals enclosing the line LINE of FILE, their values and the
+    /// definitions they depend on, to standard error if the output goes to standard output,
+    /// like --print-defs
+    #[arg(long, value_name = "FILE:LINE", value_parser = parse_line)]
+    explain: Option<(PathBuf, usize)>,
+    /// Write the Makefile rule listing the files read instead of the output
+    #[arg(short = 'M')]
+    dependencies: bool,
+    /// Write the Makefile rule to FILE, in addition to the output unless -M is given; also
+    /// spelled -MF
+    #[arg(long = "MF", value_name = "FILE")]
+    depfile: Option<PathBuf>,
+    /// Target of the Makefile rule, the input with the `sla` extension by default; also
+    /// spelled -MT
+    #[arg(long = "MT", value_name = "TARGET")]
+    dependency_target: Option<PathBuf>,
+    /// With `json`, print the definitions, locations, diagnostics, output and error as one
+    /// document to standard output, the output being null if it is written to a file
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+    #[command(flatten)]
+    sarif: SarifArgs,
+    /// Write the graph of the files including each other to FILE
+    #[arg(long, value_name = "FILE")]
+    include_graph: Option<PathBuf>,
+    /// Format of the include graph
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    graph_format: GraphFormat,
+    /// Name of the standard input in markers, locations and errors, included files are
+    /// looked up relative to it
+    #[arg(long, value_name = "PATH", default_value = "<stdin>")]
+    stdin_filename: PathBuf,
+    /// File to preprocess, `-` for standard input
+    file: Option<PathBuf>,
+}
+
+/// SARIF log option, which only exists with serde.
+#[derive(Args)]
+struct SarifArgs {
+    /// Write the warnings and errors as a SARIF 2.1.0 log to FILE
+    #[cfg(feature = "serde")]
+    #[arg(long, value_name = "FILE")]
+    sarif: Option<PathBuf>,
+}
+
+impl SarifArgs {
+    /// Writes the log of the diagnostics and the error of each root, if a file is given.
+    #[cfg(feature = "serde")]
+    fn write<'a>(
+        &self,
+        roots: impl IntoIterator<Item = (&'a [Diagnostic], Option<&'a Error>)>,
+    ) -> Result<()> {
+        if let Some(path) = &self.sarif {
+            let mut log = SarifLog::new();
+            for (diagnostics, error) in roots {
+                for diagnostic in diagnostics {
+                    log.add_diagnostic(diagnostic);
+                }
+                if let Some(error) = error {
+                    log.add_error(error);
+                }
+            }
+            std::fs::write(path, log.to_json())?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn write<'a>(
+        &self,
+        _roots: impl IntoIterator<Item = (&'a [Diagnostic], Option<&'a Error>)>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ReportFormat {
+    Text,
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+impl ReportFormat {
+    fn is_json(self) -> bool {
+        match self {
+            ReportFormat::Text => false,
+            #[cfg(feature = "serde")]
+            ReportFormat::Json => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    /// Graphviz
+    Dot,
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Preprocesses every `.slaspec` file under a directory and summarizes the results
+    Dir {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Number of files preprocessed at once, the available parallelism by default
+        #[arg(short = 'j', long)]
+        threads: Option<usize>,
+        /// Directory the outputs are written to, at the path of their input relative to DIR
+        #[arg(short, long, value_name = "DIR")]
+        output: PathBuf,
+        #[command(flatten)]
+        sarif: SarifArgs,
+        dir: PathBuf,
+    },
+    /// Removes conditionals decided by the given definitions
+    Specialize {
+        /// Known definition, `NAME` alone defines an empty value
+        #[arg(short = 'D', value_name = "NAME[=VALUE]")]
+        define: Vec<String>,
+        /// Name known to be undefined
+        #[arg(short = 'U', value_name = "NAME")]
+        undefine: Vec<String>,
+        /// Replace `$(NAME)` by the value of known definitions
+        #[arg(long)]
+        inline: bool,
+        /// Directory the specialized files are written to
+        #[arg(short, long, value_name = "DIR")]
+        output: PathBuf,
+        /// Root file, included files are written at their path relative to its directory
+        root: PathBuf,
+    },
+    /// Reports conditional branches that are never taken or always taken
+    Check {
+        /// Files to check, included files are not read
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Formats preprocessor directives in place
+    Fmt {
+        /// Only report files which are not formatted
+        #[arg(long)]
+        check: bool,
+        /// Spaces per nesting level of directives
+        #[arg(long, default_value_t = 0)]
+        indent: usize,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
 
 fn main() {
     pretty_env_logger::init();
-    let mut writer = String::new();
-    let definitions = HashMap::new();
-    let file_path = args().nth(1).unwrap();
-    let mut sleigh_preprocessor = SleighPreprocessor::new(definitions, &file_path, true);
-    if let Err(e) = sleigh_preprocessor.process(&mut writer) {
+    let cli = Cli::parse_from(gcc_style_args());
+    let result = match cli.command {
+        Some(Command::Dir {
+            input,
+            threads,
+            output,
+            sarif,
+            dir,
+        }) => preprocess_dir(input, threads, output, sarif, dir),
+        Some(Command::Specialize {
+            define,
+            undefine,
+            inline,
+            output,
+            root,
+        }) => specialize(define, undefine, inline, output, root),
+        Some(Command::Check { files }) => check(files),
+        Some(Command::Fmt {
+            check,
+            indent,
+            files,
+        }) => format(check, indent, files),
+        None => preprocess(cli.preprocess),
+    };
+    if let Err(e) = result {
         eprintln!("{}", e);
+        let code = match e {
+            Error::Io(_) => 3,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+}
+
+/// Spells gcc's `-MF` and `-MT` options the way clap reads them, where an option may be:
+/// neither after `--` nor as the value of the previous option.
+fn gcc_style_args() -> Vec<OsString> {
+    let valued = valued_options(&Cli::command());
+    let mut args = Vec::new();
+    let mut is_value = false;
+    let mut options_ended = false;
+    for arg in std::env::args_os() {
+        let arg = match arg.to_str() {
+            Some(s)
+                if !is_value
+                    && !options_ended
+                    && (s.starts_with("-MF") || s.starts_with("-MT")) =>
+            {
+                let (option, value) = s.split_at(3);
+                if value.is_empty() {
+                    format!("-{}", option).into()
+                } else {
+                    format!("-{}={}", option, value).into()
+                }
+            }
+            _ => arg,
+        };
+        if !is_value && !options_ended {
+            let s = arg.to_str().unwrap_or_default();
+            options_ended = s == "--";
+            is_value = valued.iter().any(|option| option == s);
+        } else {
+            is_value = false;
+        }
+        args.push(arg);
+    }
+    args
+}
+
+/// Spellings of the options taking a value, in any subcommand, which is then the next
+/// argument when they are given alone.
+fn valued_options(command: &clap::Command) -> Vec<String> {
+    let mut options = Vec::new();
+    for arg in command.get_arguments() {
+        if !arg.is_positional() && arg.get_action().takes_values() {
+            options.extend(arg.get_short().map(|short| format!("-{}", short)));
+            options.extend(arg.get_long().map(|long| format!("--{}", long)));
+        }
+    }
+    for subcommand in command.get_subcommands() {
+        options.extend(valued_options(subcommand));
+    }
+    options
+}
+
+fn parse_definition(definition: &str) -> std::result::Result<(String, String), String> {
+    match definition.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got \"{}\"", definition)),
+    }
+}
+
+fn parse_line(line: &str) -> std::result::Result<(PathBuf, usize), String> {
+    match line.rsplit_once(':') {
+        Some((path, line_no)) if !path.is_empty() => match line_no.parse() {
+            Ok(line_no) if line_no > 0 => Ok((path.into(), line_no)),
+            _ => Err(format!("invalid line number \"{}\"", line_no)),
+        },
+        _ => Err(format!("expected FILE:LINE, got \"{}\"", line)),
+    }
+}
+
+fn preprocess(args: PreprocessArgs) -> Result<()> {
+    let file_path = match args.file {
+        Some(file_path) => file_path,
+        None => {
+            eprintln!("no input file");
+            std::process::exit(2);
+        }
+    };
+    let definitions = args.input.definitions();
+    let is_compatible = !args.input.markers;
+    let is_stdin = file_path == Path::new("-");
+    let file_path = if is_stdin {
+        args.stdin_filename
+    } else {
+        file_path
+    };
+    let mut sleigh_preprocessor = SleighPreprocessor::new(definitions, &file_path, is_compatible)
+        .with_indented_directives(!args.input.no_indented_directives);
+    if is_stdin {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        sleigh_preprocessor = sleigh_preprocessor.with_source(source);
+    }
+    for dir in args.input.include_dirs {
+        sleigh_preprocessor = sleigh_preprocessor.with_include_dir(dir);
+    }
+    if let Some((path, line_no)) = &args.explain {
+        sleigh_preprocessor = sleigh_preprocessor.with_explain(path, *line_no);
+    }
+    let mut writer = String::new();
+    let result = sleigh_preprocessor.process(&mut writer);
+    let is_json = args.format.is_json();
+    if !is_json {
+        for diagnostic in sleigh_preprocessor.diagnostics() {
+            eprintln!("{}", diagnostic);
+        }
+    }
+    args.sarif.write(std::iter::once((
+        sleigh_preprocessor.diagnostics(),
+        result.as_ref().err(),
+    )))?;
+    #[cfg(feature = "serde")]
+    if let (Err(e), true) = (&result, is_json) {
+        print_json(&sleigh_preprocessor, None, Some(e))?;
+    }
+    result?;
+
+    if let Some(path) = args.include_graph {
+        let graph = sleigh_preprocessor.include_graph();
+        let graph = match args.graph_format {
+            GraphFormat::Dot => graph.to_dot(),
+            #[cfg(feature = "serde")]
+            GraphFormat::Json => graph.to_json(),
+        };
+        std::fs::write(path, graph)?;
+    }
+    let output = args.output.filter(|output| output != Path::new("-"));
+    if args.dependencies || args.depfile.is_some() {
+        let target = args
+            .dependency_target
+            .unwrap_or_else(|| file_path.with_extension("sla"));
+        let rule = sleigh_preprocessor.dependencies().to_makefile(target);
+        match &args.depfile {
+            Some(depfile) => {
+                std::fs::write(depfile, rule)?;
+                if args.dependencies {
+                    return Ok(());
+                }
+            }
+            // -M alone: the rule replaces the output
+            None => writer = rule,
+        }
+    }
+    #[cfg(feature = "serde")]
+    if is_json {
+        let text = match output {
+            Some(output) => {
+                std::fs::write(output, writer)?;
+                None
+            }
+            None => Some(writer),
+        };
+        return print_json(&sleigh_preprocessor, text.as_deref(), None);
+    }
+    let mut report = String::new();
+    if args.print_defs {
+        let mut definitions: Vec<_> = sleigh_preprocessor.definitions().iter().collect();
+        definitions.sort();
+        for (name, value) in definitions {
+            report.push_str(&format!("{}={}\n", name, value));
+        }
+    }
+    if args.print_locations {
+        for location in sleigh_preprocessor.locations() {
+            report.push_str(&format!(
+                "{}\t{}:{}\n",
+                location.global_line_num(),
+                location.filepath().display(),
+                location.local_line_num()
+            ));
+        }
+    }
+    if args.print_inactive {
+        for region in sleigh_preprocessor.inactive_regions().regions() {
+            report.push_str(&format!(
+                "{}:{}-{}\t{}\n",
+                region.path.display(),
+                region.start_line_no,
+                region.end_line_no,
+                region.conditional_line_no
+            ));
+        }
+    }
+    if let Some((path, line_no)) = &args.explain {
+        let explanations = sleigh_preprocessor.explanations();
+        if explanations.is_empty() {
+            report.push_str(&format!("{}:{} is not read\n", path.display(), line_no));
+        }
+        for explanation in explanations {
+            report.push_str(&explanation.to_string());
+        }
+    }
+    match output {
+        Some(output) => {
+            std::fs::write(output, writer)?;
+            std::io::stdout().write_all(report.as_bytes())?;
+        }
+        None => {
+            std::io::stdout().write_all(writer.as_bytes())?;
+            std::io::stderr().write_all(report.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    definitions: Option<BTreeMap<&'a str, &'a str>>,
+    locations: Option<&'a [Location]>,
+    inactive_regions: Option<&'a InactiveRegions>,
+    diagnostics: &'a [Diagnostic],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    explanations: &'a [Explanation],
+    output: Option<&'a str>,
+    error: Option<&'a Error>,
+}
+
+/// Prints the results of `preprocessor`, which has none but its diagnostics if it failed.
+#[cfg(feature = "serde")]
+fn print_json(
+    preprocessor: &SleighPreprocessor,
+    output: Option<&str>,
+    error: Option<&Error>,
+) -> Result<()> {
+    let succeeded = error.is_none();
+    let report = JsonReport {
+        definitions: succeeded.then(|| {
+            preprocessor
+                .definitions()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect()
+        }),
+        locations: succeeded.then(|| preprocessor.locations()),
+        inactive_regions: succeeded.then(|| preprocessor.inactive_regions()),
+        diagnostics: preprocessor.diagnostics(),
+        explanations: preprocessor.explanations(),
+        output,
+        error,
+    };
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &report).map_err(std::io::Error::from)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+fn preprocess_dir(
+    input: InputArgs,
+    threads: Option<usize>,
+    output: PathBuf,
+    sarif: SarifArgs,
+    dir: PathBuf,
+) -> Result<()> {
+    let mut paths = Vec::new();
+    let skipped = output.canonicalize().ok();
+    find_slaspecs(&dir, skipped.as_deref(), &mut paths)?;
+    paths.sort();
+    let definitions = input.definitions();
+    let roots: Vec<_> = paths
+        .iter()
+        .map(|path| Root::new(path, definitions.clone()))
+        .collect();
+    let mut batch = BatchPreprocessor::new(!input.markers)
+        .with_indented_directives(!input.no_indented_directives);
+    for include_dir in input.include_dirs {
+        batch = batch.with_include_dir(include_dir);
+    }
+    if let Some(threads) = threads {
+        batch = batch.with_threads(threads);
+    }
+
+    let start = Instant::now();
+    let results = batch.process(&roots);
+    let elapsed = start.elapsed();
+    let mut failures = 0;
+    let mut statuses = Vec::new();
+    for result in &results {
+        for diagnostic in &result.diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+        let status = match &result.output {
+            Ok(root_output) => {
+                let path = output.join(result.path.strip_prefix(&dir).unwrap());
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, &root_output.text)?;
+                "ok"
+            }
+            Err(e) => {
+                eprintln!("{}: {}", result.path.display(), e);
+                failures += 1;
+                "FAILED"
+            }
+        };
+        statuses.push(status);
+    }
+    sarif.write(
+        results
+            .iter()
+            .map(|result| (result.diagnostics.as_slice(), result.output.as_ref().err())),
+    )?;
+    println!("{:<6}  {:>10}  {:>8}  file", "status", "time", "warnings");
+    for (result, status) in results.iter().zip(statuses) {
+        println!(
+            "{:<6}  {:>7.1} ms  {:>8}  {}",
+            status,
+            result.elapsed.as_secs_f64() * 1000.0,
+            result.diagnostics.len(),
+            result.path.strip_prefix(&dir).unwrap().display()
+        );
+    }
+    println!(
+        "{} succeeded, {} failed in {:.1} ms",
+        results.len() - failures,
+        failures,
+        elapsed.as_secs_f64() * 1000.0
+    );
+    if failures > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Collects the `.slaspec` files under `dir`, except under `skipped`. Links to directories
+/// are not followed, which could loop forever.
+fn find_slaspecs(dir: &Path, skipped: Option<&Path>, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if skipped.is_none() || path.canonicalize().ok().as_deref() != skipped {
+                find_slaspecs(&path, skipped, paths)?;
+            }
+        } else if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "slaspec")
+        {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn specialize(
+    define: Vec<String>,
+    undefine: Vec<String>,
+    inline: bool,
+    output: PathBuf,
+    root: PathBuf,
+) -> Result<()> {
+    let mut known = KnownDefinitions::new();
+    for definition in define {
+        match definition.split_once('=') {
+            Some((name, value)) => known.define(name, value),
+            None => known.define(definition, ""),
+        }
+    }
+    for name in undefine {
+        known.undefine(name);
+    }
+    let tree = Specializer::new(known)
+        .with_inline_expansions(inline)
+        .specialize_tree(&root)?;
+    for include in tree.unresolved_includes() {
+        eprintln!(
+            "warning: {}:{}: include \"{}\" depends on unknown definitions, not specialized",
+            include.path.display(),
+            include.line_no,
+            include.include
+        );
+    }
+    let base_dir = root.parent().map(PathBuf::from).unwrap_or_default();
+    for path in tree.write(base_dir, output)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+fn check(files: Vec<PathBuf>) -> Result<()> {
+    let mut found = false;
+    for file in files {
+        let source = std::fs::read_to_string(&file)?;
+        for finding in check_conditionals(&source, &file)? {
+            println!(
+                "{}:{}: warning: {}",
+                file.display(),
+                finding.line_no,
+                finding
+            );
+            found = true;
+        }
+    }
+    if found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn format(check: bool, indent: usize, files: Vec<PathBuf>) -> Result<()> {
+    let formatter = Formatter::new().with_indent(indent);
+    let mut unformatted = false;
+    for file in files {
+        let source = std::fs::read_to_string(&file)?;
+        let formatted = formatter.format(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", file.display());
+            unformatted = true;
+        } else {
+            std::fs::write(&file, formatted)?;
+        }
+    }
+    if unformatted {
         std::process::exit(1);
     }
-    println!("{:#?}", sleigh_preprocessor.definitions());
-    println!("{:#?}", sleigh_preprocessor.locations());
-    let mut new_file =
-        File::create(std::path::PathBuf::from(&file_path).with_extension("sla")).unwrap();
-    new_file.write_all(&writer.into_bytes()).unwrap();
+    Ok(())
 }
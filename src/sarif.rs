@@ -0,0 +1,173 @@
+//! SARIF 2.1.0 log of the errors and warnings of preprocessing, for code scanning tools.
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::errors::Error;
+use crate::location::IncludeSite;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Results of preprocessing one or more roots. Rules are named after the kinds of errors and
+/// diagnostics, and results in included files are related to the includes leading to them.
+#[derive(Debug, Default)]
+pub struct SarifLog {
+    rules: Vec<&'static str>,
+    results: Vec<Value>,
+}
+
+impl SarifLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a diagnostic, located at the first character of its line which is not blank.
+    pub fn add_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let column = diagnostic
+            .line()
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count()
+            + 1;
+        let location = Some((diagnostic.path(), diagnostic.line_no(), Some(column)));
+        self.add_result(
+            diagnostic.kind().as_str(),
+            diagnostic.severity(),
+            diagnostic.message(),
+            location,
+            diagnostic.includes(),
+        );
+    }
+
+    /// Adds an error, located if it is a preprocessor error raised in a file.
+    pub fn add_error(&mut self, error: &Error) {
+        match error {
+            Error::Preprocessor(e) => {
+                let location = e.file_path().map(|path| (path, e.line_no(), e.column()));
+                self.add_result(
+                    error.kind().as_str(),
+                    Severity::Error,
+                    e.message(),
+                    location,
+                    e.includes(),
+                );
+            }
+            _ => self.add_result(
+                error.kind().as_str(),
+                Severity::Error,
+                &error.to_string(),
+                None,
+                &[],
+            ),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let rules: Vec<_> = self.rules.iter().map(|id| json!({ "id": id })).collect();
+        let log = json!({
+            "$schema": SCHEMA,
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules,
+                    },
+                },
+                "columnKind": "unicodeCodePoints",
+                "results": self.results,
+            }],
+        });
+        let mut json = serde_json::to_string_pretty(&log).unwrap();
+        json.push('\n');
+        json
+    }
+
+    fn add_result(
+        &mut self,
+        rule: &'static str,
+        severity: Severity,
+        message: &str,
+        location: Option<(&Path, usize, Option<usize>)>,
+        includes: &[IncludeSite],
+    ) {
+        let rule_index = match self.rules.iter().position(|&id| id == rule) {
+            Some(index) => index,
+            None => {
+                self.rules.push(rule);
+                self.rules.len() - 1
+            }
+        };
+        let mut result = json!({
+            "ruleId": rule,
+            "ruleIndex": rule_index,
+            "level": severity.to_string(),
+            "message": { "text": message },
+        });
+        if let Some((path, line_no, column)) = location {
+            result["locations"] = json!([physical_location(path, line_no, column)]);
+            // the innermost include first, as compilers list them
+            let included: Vec<_> = includes
+                .iter()
+                .skip(1)
+                .map(|site| site.path.as_path())
+                .chain(std::iter::once(path))
+                .collect();
+            let related: Vec<_> = includes
+                .iter()
+                .zip(included)
+                .rev()
+                .enumerate()
+                .map(|(id, (site, included))| {
+                    let mut location = physical_location(&site.path, site.line_no, None);
+                    location["id"] = json!(id);
+                    location["message"] =
+                        json!({ "text": format!("{} included here", included.display()) });
+                    location
+                })
+                .collect();
+            if !related.is_empty() {
+                result["relatedLocations"] = json!(related);
+            }
+        }
+        self.results.push(result);
+    }
+}
+
+fn physical_location(path: &Path, line_no: usize, column: Option<usize>) -> Value {
+    let mut region = json!({ "startLine": line_no });
+    if let Some(column) = column {
+        region["startColumn"] = json!(column);
+    }
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": uri(path) },
+            "region": region,
+        },
+    })
+}
+
+/// URI reference of `path`: relative paths stay relative to the working directory, which is
+/// where code scanning tools expect the checkout root.
+fn uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let absolute = path.starts_with('/') || path.as_bytes().get(1) == Some(&b':');
+    let mut uri = String::from(match (absolute, path.starts_with('/')) {
+        (false, _) => "",
+        (true, true) => "file://",
+        (true, false) => "file:///",
+    });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            b':' if absolute => uri.push(':'),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
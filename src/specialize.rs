@@ -0,0 +1,542 @@
+//! Unifdef-style specialization of SLEIGH sources for a fixed set of definitions.
+//!
+//! Conditionals decided by the known definitions are removed together with their dead
+//! branches, undecided conditionals are kept with simplified conditions. Every other line,
+//! including comments and formatting, is copied unchanged.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use log::trace;
+use regex::Captures;
+
+use crate::boolean_expression::{Expr, KnownDefinitions, UnaryOp};
+use crate::directive::{self, directive_text, Directive};
+use crate::errors::{Error, ErrorKind, PreprocessorError, Result};
+use crate::escape::unescape;
+use crate::EXPANSION_RE;
+
+#[derive(Debug, Default)]
+pub struct Specializer {
+    known: KnownDefinitions,
+    inline_expansions: bool,
+}
+
+/// Specialized files of [`Specializer::specialize_tree`].
+#[derive(Debug, Default)]
+pub struct SpecializedTree {
+    files: Vec<(PathBuf, String)>,
+    unresolved_includes: Vec<UnresolvedInclude>,
+}
+
+/// Kept `@include` whose path depends on definitions that are not known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnresolvedInclude {
+    pub path: PathBuf,
+    pub line_no: usize,
+    pub include: String,
+}
+
+impl Specializer {
+    pub fn new(known: KnownDefinitions) -> Self {
+        Self {
+            known,
+            inline_expansions: false,
+        }
+    }
+
+    /// Replaces `$(NAME)` in kept lines by the value of `NAME` if it is known.
+    pub fn with_inline_expansions(mut self, inline_expansions: bool) -> Self {
+        self.inline_expansions = inline_expansions;
+        self
+    }
+
+    /// Specializes a single source. Definitions it makes are taken into account, included
+    /// files are not read.
+    pub fn specialize<P: AsRef<Path>>(&self, source: &str, path: P) -> Result<String> {
+        let mut known = self.known.clone();
+        let mut file = FileSpecializer::new(self, path.as_ref(), &mut known, true, 1);
+        file.run(source, &mut |_, _, _, _| Ok(()))?;
+        Ok(file.output)
+    }
+
+    /// Specializes `root` and, in include order, every file it includes from kept lines.
+    ///
+    /// Definitions are followed across files like the preprocessor does. A file included
+    /// several times has to specialize to the same text each time.
+    pub fn specialize_tree<P: AsRef<Path>>(&self, root: P) -> Result<SpecializedTree> {
+        let mut tree = TreeSpecializer::default();
+        let mut known = self.known.clone();
+        let (index, output) =
+            tree.specialize_file(self, &normalize(root.as_ref()), &mut known, true, 1)?;
+        tree.files[index].1 = output;
+        Ok(SpecializedTree {
+            files: tree.files,
+            unresolved_includes: tree.unresolved_includes,
+        })
+    }
+}
+
+impl SpecializedTree {
+    /// Paths and specialized contents, the root file first.
+    pub fn files(&self) -> &[(PathBuf, String)] {
+        &self.files
+    }
+
+    /// Files behind these includes were not specialized and definitions they make are not
+    /// taken into account.
+    pub fn unresolved_includes(&self) -> &[UnresolvedInclude] {
+        &self.unresolved_includes
+    }
+
+    /// Writes every file under `output_dir` at its path relative to `base_dir`, returns the
+    /// written paths.
+    pub fn write<P, Q>(&self, base_dir: P, output_dir: Q) -> Result<Vec<PathBuf>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let base_dir = normalize(base_dir.as_ref());
+        let mut written = Vec::with_capacity(self.files.len());
+        for (path, content) in &self.files {
+            let relative = path.strip_prefix(&base_dir).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{} is outside of {}", path.display(), base_dir.display()),
+                )
+            })?;
+            let output_path = output_dir.as_ref().join(relative);
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&output_path, content)?;
+            written.push(output_path);
+        }
+        Ok(written)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TreeSpecializer {
+    files: Vec<(PathBuf, String)>,
+    unresolved_includes: Vec<UnresolvedInclude>,
+    /// Index in `files` and whether the file has been specialized already.
+    seen: HashMap<PathBuf, (usize, bool)>,
+    /// Files being specialized, to detect recursive includes.
+    active: Vec<PathBuf>,
+}
+
+impl TreeSpecializer {
+    fn specialize_file(
+        &mut self,
+        specializer: &Specializer,
+        path: &Path,
+        known: &mut KnownDefinitions,
+        certain: bool,
+        overall_line_no: usize,
+    ) -> Result<(usize, String)> {
+        trace!("specializing {}", path.display());
+        let source = fs::read_to_string(path)?;
+        let index = match self.seen.get(path) {
+            Some(&(index, _)) => index,
+            None => {
+                // reserve the slot now to list files in include order
+                self.files.push((path.to_path_buf(), String::new()));
+                let index = self.files.len() - 1;
+                self.seen.insert(path.to_path_buf(), (index, false));
+                index
+            }
+        };
+        self.active.push(path.to_path_buf());
+        let mut file = FileSpecializer::new(specializer, path, known, certain, overall_line_no);
+        file.run(&source, &mut |file, line, include, certain| {
+            let included = match file.resolve_include(include) {
+                Some(included) => included,
+                None => {
+                    self.unresolved_includes.push(UnresolvedInclude {
+                        path: file.path.to_path_buf(),
+                        line_no: file.line_no,
+                        include: include.to_string(),
+                    });
+                    return Ok(());
+                }
+            };
+            if self.active.contains(&included) {
+                return Err(file.error(ErrorKind::RecursiveInclude, "recursive include", line));
+            }
+            let overall_line_no = file.overall_line_no;
+            let (index, output) =
+                self.specialize_file(specializer, &included, file.known, certain, overall_line_no)?;
+            let (_, specialized) = self.seen.get_mut(&included).unwrap();
+            if *specialized && self.files[index].1 != output {
+                let message = format!(
+                    "{} specializes differently than where it was included before",
+                    included.display()
+                );
+                return Err(file.error(ErrorKind::InconsistentSpecialization, &message, line));
+            }
+            *specialized = true;
+            self.files[index].1 = output;
+            Ok(())
+        })?;
+        self.active.pop();
+        Ok((index, file.output))
+    }
+}
+
+/// What happens to the lines of the current branch of a conditional.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Branch {
+    /// Never taken: the lines are removed.
+    Dropped,
+    /// Always taken: the directive is removed, the lines are kept.
+    Taken,
+    /// Undecided: the directive and the lines are kept.
+    Kept,
+}
+
+#[derive(Debug)]
+struct Frame {
+    branch: Branch,
+    /// Whether the lines around the conditional are kept.
+    outer_kept: bool,
+    /// An earlier branch is always taken, so no later one can be.
+    resolved: bool,
+    /// Number of branches kept as conditionals, the `@endif` is kept if there is any.
+    kept_branches: usize,
+    saw_else: bool,
+}
+
+/// Called for every kept `@include` with the line, the include path and whether the line is
+/// always kept.
+type IncludeHandler<'h> = dyn FnMut(&mut FileSpecializer, &str, &str, bool) -> Result<()> + 'h;
+
+struct FileSpecializer<'a> {
+    specializer: &'a Specializer,
+    path: &'a Path,
+    known: &'a mut KnownDefinitions,
+    /// Whether the file itself is always included.
+    certain: bool,
+    stack: Vec<Frame>,
+    line_no: usize,
+    /// Line number counted as the preprocessor does, from the first line of the root.
+    overall_line_no: usize,
+    output: String,
+}
+
+impl<'a> FileSpecializer<'a> {
+    fn new(
+        specializer: &'a Specializer,
+        path: &'a Path,
+        known: &'a mut KnownDefinitions,
+        certain: bool,
+        first_overall_line_no: usize,
+    ) -> Self {
+        Self {
+            specializer,
+            path,
+            known,
+            certain,
+            stack: Vec::new(),
+            line_no: 0,
+            overall_line_no: first_overall_line_no - 1,
+            output: String::new(),
+        }
+    }
+
+    fn run(&mut self, source: &str, on_include: &mut IncludeHandler) -> Result<()> {
+        for line in source.split_inclusive('\n') {
+            self.line_no += 1;
+            self.overall_line_no += 1;
+            let content = line
+                .strip_suffix('\n')
+                .map(|l| l.strip_suffix('\r').unwrap_or(l))
+                .unwrap_or(line);
+            self.line(content, &line[content.len()..], on_include)?;
+        }
+        if !self.stack.is_empty() {
+            return Err(self.error(ErrorKind::UnbalancedConditional, "missing @endif", ""));
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str, newline: &str, on_include: &mut IncludeHandler) -> Result<()> {
+        let directive = match directive_text(line, true) {
+            Some(directive) => directive,
+            None => {
+                if self.is_kept() {
+                    let line = self.inline(line);
+                    self.emit(&line, newline);
+                }
+                return Ok(());
+            }
+        };
+        let parsed = Directive::parse(directive).ok_or_else(|| {
+            self.error(
+                ErrorKind::UnrecognizedDirective,
+                "unrecognized preprocessor directive",
+                line,
+            )
+        })?;
+        match parsed {
+            Directive::Include(include) => {
+                if self.is_kept() {
+                    let inlined = self.inline(line);
+                    self.emit(&inlined, newline);
+                    let certain = self.is_certain();
+                    on_include(self, line, include, certain)?;
+                }
+            }
+            Directive::Define {
+                name,
+                value,
+                quoted,
+            } => {
+                if self.is_kept() {
+                    if self.is_certain() {
+                        let value = match value {
+                            Some(value) if quoted => unescape(value).map_err(|e| {
+                                self.error(ErrorKind::InvalidEscape, &e.to_string(), line)
+                            })?,
+                            value => value.unwrap_or_default().to_string(),
+                        };
+                        self.known.define(name, value);
+                    } else {
+                        self.known.forget(name);
+                    }
+                    self.emit(line, newline);
+                }
+            }
+            Directive::Undef(name) => {
+                if self.is_kept() {
+                    if self.is_certain() {
+                        self.known.undefine(name);
+                    } else {
+                        self.known.forget(name);
+                    }
+                    self.emit(line, newline);
+                }
+            }
+            Directive::Ifdef(name) => {
+                self.enter_if();
+                self.branch(line, newline, "if", |_| Ok(Expr::Defined(name.to_string())))?;
+            }
+            Directive::Ifndef(name) => {
+                self.enter_if();
+                self.branch(line, newline, "if", |_| {
+                    let defined = Expr::Defined(name.to_string());
+                    Ok(Expr::Unary(UnaryOp::Not, Box::new(defined)))
+                })?;
+            }
+            Directive::If(expression) => {
+                self.enter_if();
+                self.branch(line, newline, "if", |this| this.parse(expression, line))?;
+            }
+            Directive::Elif(expression) => {
+                match self.stack.last() {
+                    None => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "elif outside of IF* directive",
+                            line,
+                        ))
+                    }
+                    Some(frame) if frame.saw_else => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "already saw else directive",
+                            line,
+                        ))
+                    }
+                    _ => {}
+                }
+                self.branch(line, newline, "elif", |this| this.parse(expression, line))?;
+            }
+            Directive::Else => {
+                match self.stack.last_mut() {
+                    None => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "else outside of IF* directive",
+                            line,
+                        ))
+                    }
+                    Some(frame) if frame.saw_else => {
+                        return Err(self.error(
+                            ErrorKind::UnbalancedConditional,
+                            "duplicate else directive",
+                            line,
+                        ))
+                    }
+                    Some(frame) => frame.saw_else = true,
+                }
+                self.branch(line, newline, "else", |_| Ok(Expr::Bool(true)))?;
+            }
+            Directive::Endif => {
+                let frame = self.stack.pop().ok_or_else(|| {
+                    self.error(
+                        ErrorKind::UnbalancedConditional,
+                        "not in IF* directive",
+                        line,
+                    )
+                })?;
+                if frame.outer_kept && frame.kept_branches > 0 {
+                    self.emit(line, newline);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn enter_if(&mut self) {
+        let outer_kept = self.is_kept();
+        self.stack.push(Frame {
+            branch: Branch::Dropped,
+            outer_kept,
+            resolved: false,
+            kept_branches: 0,
+            saw_else: false,
+        });
+    }
+
+    /// Starts the next branch of the innermost conditional, written with `keyword` in the
+    /// source. The condition is only parsed if the branch may be taken.
+    fn branch<F>(&mut self, line: &str, newline: &str, keyword: &str, condition: F) -> Result<()>
+    where
+        F: FnOnce(&Self) -> Result<Expr>,
+    {
+        let frame = self.stack.last().unwrap();
+        if !frame.outer_kept || frame.resolved {
+            self.stack.last_mut().unwrap().branch = Branch::Dropped;
+            return Ok(());
+        }
+        let first_kept = frame.kept_branches == 0;
+        let condition = condition(self)?;
+        let simplified = condition.simplify(self.known);
+        trace!("{}: {} -> {}", self.line_no, condition, simplified);
+        let value = simplified.as_condition();
+        let frame = self.stack.last_mut().unwrap();
+        frame.resolved = value == Some(true);
+        let new_keyword = match value {
+            Some(false) => {
+                frame.branch = Branch::Dropped;
+                return Ok(());
+            }
+            Some(true) if first_kept => {
+                frame.branch = Branch::Taken;
+                return Ok(());
+            }
+            Some(true) => "else",
+            None if first_kept => "if",
+            None => "elif",
+        };
+        frame.branch = Branch::Kept;
+        frame.kept_branches += 1;
+        if new_keyword == keyword && simplified == condition {
+            self.emit(line, newline);
+        } else if value.is_some() {
+            let line = rewrite_directive(line, "@else");
+            self.emit(&line, newline);
+        } else {
+            let line = rewrite_directive(line, &format!("@{} {}", new_keyword, simplified));
+            self.emit(&line, newline);
+        }
+        Ok(())
+    }
+
+    fn is_kept(&self) -> bool {
+        self.stack
+            .iter()
+            .all(|frame| frame.branch != Branch::Dropped)
+    }
+
+    /// Whether the current line is kept whatever the unknown definitions are.
+    fn is_certain(&self) -> bool {
+        self.certain && self.stack.iter().all(|frame| frame.branch == Branch::Taken)
+    }
+
+    fn parse(&self, expression: &str, line: &str) -> Result<Expr> {
+        Expr::parse(expression)
+            .map_err(|e| self.error(e.kind(), &format!("parser error: {}", e), line))
+    }
+
+    fn inline(&self, line: &str) -> String {
+        if !self.specializer.inline_expansions {
+            return line.to_string();
+        }
+        EXPANSION_RE
+            .replace_all(line, |m: &Captures| match self.known.get(&m[1]) {
+                Some(Some(value)) => value.to_string(),
+                _ => m[0].to_string(),
+            })
+            .into_owned()
+    }
+
+    /// Path of an included file, `None` if it depends on unknown definitions.
+    fn resolve_include(&self, include: &str) -> Option<PathBuf> {
+        let mut resolved = true;
+        let include =
+            EXPANSION_RE.replace_all(include, |m: &Captures| match self.known.get(&m[1]) {
+                Some(Some(value)) => value.to_string(),
+                _ => {
+                    resolved = false;
+                    String::new()
+                }
+            });
+        if !resolved {
+            return None;
+        }
+        let include = PathBuf::from(include.as_ref());
+        match self.path.parent() {
+            Some(parent) if include.is_relative() => Some(normalize(&parent.join(include))),
+            _ => Some(normalize(&include)),
+        }
+    }
+
+    fn emit(&mut self, line: &str, newline: &str) {
+        self.output.push_str(line);
+        self.output.push_str(newline);
+    }
+
+    fn error(&self, kind: ErrorKind, message: &str, line: &str) -> Error {
+        PreprocessorError::in_file(
+            kind,
+            message,
+            self.path,
+            self.line_no,
+            self.overall_line_no,
+            line,
+        )
+        .into()
+    }
+}
+
+/// Replaces the directive on `line`, keeping its indentation and trailing comment.
+fn rewrite_directive(line: &str, directive: &str) -> String {
+    let indentation = &line[..line.len() - line.trim_start().len()];
+    match directive::comment_start(line) {
+        Some(comment) => format!("{}{} {}", indentation, directive, &line[comment..]),
+        None => format!("{}{}", indentation, directive),
+    }
+}
+
+/// Removes `.` and resolves `..` components without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,240 @@
+//! Lossless syntax tree of the preprocessor-level structure of a source.
+//!
+//! The tree is built without evaluating anything: text runs, directives and conditional blocks
+//! are kept with their spans, so the source can be looked at for any set of definitions and
+//! printed back byte for byte.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::directive::{comment_start, directive_text};
+
+/// Syntax tree of a source, borrowing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree<'a> {
+    source: &'a str,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Consecutive lines which are not directives, comment lines included.
+    Text(Range<usize>),
+    /// Directive outside of a conditional block structure, including an `@elif`, `@else` or
+    /// `@endif` without a matching `@if`.
+    Directive(DirectiveNode),
+    Conditional(Conditional),
+}
+
+/// Line holding a directive, spans are byte offsets in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectiveNode {
+    pub kind: DirectiveKind,
+    pub line_no: usize,
+    /// The whole line with its line terminator.
+    pub span: Range<usize>,
+    /// Name of the directive without the `@` and the indentation before it.
+    pub name: Range<usize>,
+    /// Arguments after the name, without surrounding whitespace and comment.
+    pub arguments: Range<usize>,
+    /// Comment at the end of the line, from the `#`.
+    pub comment: Option<Range<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectiveKind {
+    Include,
+    Define,
+    Undef,
+    Ifdef,
+    Ifndef,
+    If,
+    Elif,
+    Else,
+    Endif,
+    /// Line starting with `@` which is not a known directive.
+    Unknown,
+}
+
+/// `@if`, `@ifdef` or `@ifndef` block with its `@elif` and `@else` branches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conditional {
+    pub branches: Vec<ConditionalBranch>,
+    /// `None` if the source ends before the `@endif`.
+    pub endif: Option<DirectiveNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionalBranch {
+    pub directive: DirectiveNode,
+    pub body: Vec<Node>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    pub fn parse(source: &'a str) -> Self {
+        // innermost open conditional last, the root nodes first
+        let mut stack: Vec<Conditional> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut offset = 0;
+        for (i, line) in source.split_inclusive('\n').enumerate() {
+            let span = offset..offset + line.len();
+            offset = span.end;
+            let in_conditional = !stack.is_empty();
+            let current = match stack.last_mut() {
+                Some(conditional) => &mut conditional.branches.last_mut().unwrap().body,
+                None => &mut nodes,
+            };
+            let directive = match DirectiveNode::parse(line, span.clone(), i + 1) {
+                Some(directive) => directive,
+                None => {
+                    match current.last_mut() {
+                        Some(Node::Text(text)) => text.end = span.end,
+                        _ => current.push(Node::Text(span)),
+                    }
+                    continue;
+                }
+            };
+            match directive.kind {
+                DirectiveKind::Ifdef | DirectiveKind::Ifndef | DirectiveKind::If => {
+                    stack.push(Conditional {
+                        branches: vec![ConditionalBranch {
+                            directive,
+                            body: Vec::new(),
+                        }],
+                        endif: None,
+                    });
+                }
+                DirectiveKind::Elif | DirectiveKind::Else if in_conditional => {
+                    stack.last_mut().unwrap().branches.push(ConditionalBranch {
+                        directive,
+                        body: Vec::new(),
+                    });
+                }
+                DirectiveKind::Endif if in_conditional => {
+                    let mut conditional = stack.pop().unwrap();
+                    conditional.endif = Some(directive);
+                    close(&mut stack, &mut nodes, conditional);
+                }
+                _ => current.push(Node::Directive(directive)),
+            }
+        }
+        // blocks left open at the end of the source
+        while let Some(conditional) = stack.pop() {
+            close(&mut stack, &mut nodes, conditional);
+        }
+        Self { source, nodes }
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Text of a span of the source.
+    pub fn text(&self, span: &Range<usize>) -> &'a str {
+        &self.source[span.clone()]
+    }
+}
+
+/// Adds a finished conditional to the body it is nested in.
+fn close(stack: &mut [Conditional], nodes: &mut Vec<Node>, conditional: Conditional) {
+    let parent = match stack.last_mut() {
+        Some(parent) => &mut parent.branches.last_mut().unwrap().body,
+        None => nodes,
+    };
+    parent.push(Node::Conditional(conditional));
+}
+
+impl fmt::Display for SyntaxTree<'_> {
+    /// Prints the source back from the spans of the nodes.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_nodes(f: &mut fmt::Formatter<'_>, source: &str, nodes: &[Node]) -> fmt::Result {
+            for node in nodes {
+                match node {
+                    Node::Text(span) => f.write_str(&source[span.clone()])?,
+                    Node::Directive(directive) => f.write_str(&source[directive.span.clone()])?,
+                    Node::Conditional(conditional) => {
+                        for branch in &conditional.branches {
+                            f.write_str(&source[branch.directive.span.clone()])?;
+                            write_nodes(f, source, &branch.body)?;
+                        }
+                        if let Some(endif) = &conditional.endif {
+                            f.write_str(&source[endif.span.clone()])?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        write_nodes(f, self.source, &self.nodes)
+    }
+}
+
+impl Node {
+    /// Span of the node in the source, for a conditional from its first directive to the end
+    /// of its `@endif` or of its last branch.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Self::Text(span) => span.clone(),
+            Self::Directive(directive) => directive.span.clone(),
+            Self::Conditional(conditional) => {
+                let start = conditional.branches[0].directive.span.start;
+                let end = match &conditional.endif {
+                    Some(endif) => endif.span.end,
+                    None => {
+                        let last = conditional.branches.last().unwrap();
+                        last.body
+                            .last()
+                            .map_or(last.directive.span.end, |node| node.span().end)
+                    }
+                };
+                start..end
+            }
+        }
+    }
+}
+
+impl DirectiveNode {
+    /// Recognizes a directive line the way the preprocessor does, `line` being the text of
+    /// `span` with its line terminator.
+    fn parse(line: &str, span: Range<usize>, line_no: usize) -> Option<Self> {
+        let content = line.trim_end_matches(&['\r', '\n'][..]);
+        directive_text(content, true)?;
+        let comment = comment_start(content);
+        let code = &content[..comment.unwrap_or(content.len())];
+        let at = code.find('@').unwrap();
+        let name_end = code[at + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(code.len(), |i| at + 1 + i);
+        let arguments = code[name_end..].trim();
+        let arguments_start = if arguments.is_empty() {
+            name_end
+        } else {
+            name_end + code[name_end..].find(arguments).unwrap()
+        };
+        let kind = match &code[at + 1..name_end] {
+            "include" => DirectiveKind::Include,
+            "define" => DirectiveKind::Define,
+            "undef" => DirectiveKind::Undef,
+            "ifdef" => DirectiveKind::Ifdef,
+            "ifndef" => DirectiveKind::Ifndef,
+            "if" => DirectiveKind::If,
+            "elif" => DirectiveKind::Elif,
+            "else" => DirectiveKind::Else,
+            "endif" => DirectiveKind::Endif,
+            _ => DirectiveKind::Unknown,
+        };
+        let start = span.start;
+        Some(Self {
+            kind,
+            line_no,
+            span,
+            name: start + at + 1..start + name_end,
+            arguments: start + arguments_start..start + arguments_start + arguments.len(),
+            comment: comment.map(|comment| start + comment..start + content.len()),
+        })
+    }
+}
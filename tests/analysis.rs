@@ -0,0 +1,128 @@
+use sleigh_preprocessor::analysis::{check_conditionals, Finding, FindingKind};
+
+fn check(source: &str) -> Vec<(FindingKind, usize)> {
+    check_conditionals(source, "test.slaspec")
+        .unwrap()
+        .into_iter()
+        .map(|finding| (finding.kind, finding.line_no))
+        .collect()
+}
+
+#[test]
+fn unreachable_branches() {
+    let source = "\
+@if X == \"a\" || defined(Y)
+@elif X == \"a\"
+@elif X == \"b\" && X == \"c\"
+@elif X == \"b\"
+@endif
+@ifdef Y
+@if !defined(Y)
+@endif
+@elif defined(Y) && defined(Z)
+@endif
+";
+    assert_eq!(
+        check(source),
+        vec![
+            (FindingKind::Unreachable, 2),
+            (FindingKind::Unreachable, 3),
+            (FindingKind::Unreachable, 7),
+            (FindingKind::Unreachable, 9),
+        ]
+    );
+}
+
+#[test]
+fn duplicate_conditions() {
+    let source = "\
+@ifdef A
+@elif defined(B)
+@elif defined(A)
+@endif
+";
+    let findings = check_conditionals(source, "test.slaspec").unwrap();
+    assert_eq!(
+        findings,
+        vec![Finding {
+            kind: FindingKind::DuplicateCondition,
+            line_no: 3,
+            line: "@elif defined(A)".to_string(),
+            related_line_no: Some(1),
+        }]
+    );
+    assert_eq!(
+        findings[0].to_string(),
+        "condition duplicates the one at line 1: @elif defined(A)"
+    );
+}
+
+#[test]
+fn always_true_conditions() {
+    let source = "\
+@if defined(A) || !defined(A)
+@else
+@endif
+@ifdef A
+@if defined(A) && (X == \"1\" || X != \"1\")
+@endif
+@endif
+@if X == \"a\" && Y
+@elif X != \"a\" || !Y
+@endif
+";
+    assert_eq!(
+        check(source),
+        vec![
+            (FindingKind::AlwaysTrue, 1),
+            (FindingKind::Unreachable, 2),
+            (FindingKind::AlwaysTrue, 5),
+            (FindingKind::AlwaysTrue, 9),
+        ]
+    );
+}
+
+#[test]
+fn reachable_branches() {
+    // a value may equal both a string and an integer, redefined names are new values
+    let source = "\
+@if X == \"01\"
+@elif X == 1
+@endif
+@ifdef A
+@undef A
+@ifdef A
+@endif
+@endif
+@if X == 1 || Y == 2
+@elif Y == \"2\"
+@else
+@endif
+";
+    assert_eq!(check(source), vec![]);
+}
+
+#[test]
+fn check_errors() {
+    let error = check_conditionals("@else\n", "test.slaspec").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Preprocessor error: else outside of IF* directive at test.slaspec:1(1): @else"
+    );
+    assert!(check_conditionals("@if (\n@endif\n", "test.slaspec").is_err());
+    let error = check_conditionals("@ifdef A\n@else\n@elif B\n@endif\n", "test.slaspec");
+    assert_eq!(
+        error.unwrap_err().to_string(),
+        "Preprocessor error: already saw else directive at test.slaspec:3(3): @elif B"
+    );
+    let error = check_conditionals("@ifdef A\n@else\n@else\n@endif\n", "test.slaspec");
+    assert_eq!(
+        error.unwrap_err().to_string(),
+        "Preprocessor error: duplicate else directive at test.slaspec:3(3): @else"
+    );
+    let error = check_conditionals("@ifdef A\nx\n", "test.slaspec").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Preprocessor error: missing @endif at test.slaspec:2(2): "
+    );
+}
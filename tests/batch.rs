@@ -0,0 +1,82 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use common::resources;
+use sleigh_preprocessor::batch::{BatchPreprocessor, Root};
+use sleigh_preprocessor::cache::IncludeCache;
+use sleigh_preprocessor::location::Location;
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn positions(locations: &[Location]) -> Vec<(&Path, usize, usize)> {
+    locations
+        .iter()
+        .map(|location| {
+            (
+                location.filepath(),
+                location.local_line_num(),
+                location.global_line_num(),
+            )
+        })
+        .collect()
+}
+
+fn roots() -> Vec<Root> {
+    let mut definitions = HashMap::new();
+    definitions.insert("REPLACE".to_string(), "includes".to_string());
+    let mut roots = vec![Root::new(resources("include.input"), definitions.clone())];
+    for name in &["a", "b", "c", "a", "b", "c"] {
+        roots.push(Root::new(
+            resources(&format!("cache/{}.slaspec", name)),
+            HashMap::new(),
+        ));
+    }
+    roots.push(Root::new(resources("include.input"), HashMap::new()));
+    roots.push(Root::new(resources("indented.input"), definitions));
+    roots
+}
+
+#[test]
+fn batch_matches_sequential_runs() {
+    let roots = roots();
+    let cache = IncludeCache::new();
+    let results = BatchPreprocessor::new(false)
+        .with_threads(4)
+        .with_include_cache(cache.clone())
+        .process(&roots);
+    assert_eq!(results.len(), roots.len());
+    for (root, result) in roots.iter().zip(&results) {
+        assert_eq!(result.path, root.path);
+        let mut preprocessor = SleighPreprocessor::new(root.definitions.clone(), &root.path, false);
+        let mut text = String::new();
+        match (preprocessor.process(&mut text), &result.output) {
+            (Ok(()), Ok(output)) => {
+                assert_eq!(output.text, text);
+                assert_eq!(&output.definitions, preprocessor.definitions());
+                assert_eq!(
+                    positions(&output.locations),
+                    positions(preprocessor.locations())
+                );
+            }
+            (Err(expected), Err(error)) => assert_eq!(error.to_string(), expected.to_string()),
+            (expected, output) => panic!("{:?} instead of {:?}", output, expected),
+        }
+        assert_eq!(result.diagnostics, preprocessor.diagnostics());
+    }
+    assert!(cache.reused_outputs() > 0);
+}
+
+#[test]
+fn batch_reports_each_failure() {
+    let results = BatchPreprocessor::new(true).process(&roots());
+    let failures: Vec<_> = results
+        .iter()
+        .filter(|result| result.output.is_err())
+        .map(|result| result.path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(failures, ["include.input"]);
+    let indented = results.last().unwrap();
+    assert!(indented.output.is_ok());
+    assert!(!indented.diagnostics.is_empty());
+}
@@ -1,6 +1,10 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
-use sleigh_preprocessor::boolean_expression::parse_boolean_expression;
+use sleigh_preprocessor::boolean_expression::{
+    evaluate_boolean_expression, parse_boolean_expression, BinaryOp, Context, Expr, Functions,
+    KnownDefinitions, Value,
+};
 use sleigh_preprocessor::errors::Error;
 
 #[test]
@@ -101,3 +105,388 @@ fn complicated() {
     definitions.insert("E".to_string(), "E".to_string());
     assert!(!parse_boolean_expression(input, &definitions).unwrap());
 }
+
+#[test]
+fn bitwise_and() {
+    let input = "(FEATURES & 0x4) != 0";
+    let mut definitions = HashMap::new();
+    definitions.insert("FEATURES".to_string(), "0x6".to_string());
+    assert!(parse_boolean_expression(input, &definitions).unwrap());
+    definitions.insert("FEATURES".to_string(), "3".to_string());
+    assert!(!parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn arithmetic_precedence() {
+    let input = "1 + 2 * 3 == 7 && (1 << 4 | 1) == 17 && 17 % 5 - 10 / 3 == -1";
+    assert!(parse_boolean_expression(input, &Default::default()).unwrap());
+}
+
+#[test]
+fn relational() {
+    let input = "SIZE >= 32 && SIZE < 64";
+    let mut definitions = HashMap::new();
+    definitions.insert("SIZE".to_string(), "32".to_string());
+    assert!(parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn integer_condition() {
+    let input = "FEATURES & 1";
+    let mut definitions = HashMap::new();
+    definitions.insert("FEATURES".to_string(), "2".to_string());
+    assert!(!parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn string_compared_as_integer() {
+    let input = r#"VERSION == 0x10 && VERSION != "16""#;
+    let mut definitions = HashMap::new();
+    definitions.insert("VERSION".to_string(), "16".to_string());
+    assert!(!parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn type_error() {
+    let input = r#"PROCESSOR + 1 == 2"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("PROCESSOR".to_string(), "PIC_16".to_string());
+    assert_eq!(
+        parse_boolean_expression(input, &definitions).err().unwrap(),
+        Error::Type("\"PIC_16\" is not an integer".to_string())
+    );
+}
+
+#[test]
+fn string_condition() {
+    let input = r#"PROCESSOR"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("PROCESSOR".to_string(), "PIC_16".to_string());
+    assert_eq!(
+        parse_boolean_expression(input, &definitions).err().unwrap(),
+        Error::Type("expected a condition, found string \"PIC_16\"".to_string())
+    );
+}
+
+#[test]
+fn division_by_zero() {
+    let input = "1 / 0 == 0";
+    assert_eq!(
+        parse_boolean_expression(input, &Default::default())
+            .err()
+            .unwrap(),
+        Error::Arithmetic("division by zero in `/`".to_string())
+    );
+}
+
+#[test]
+fn regex_match() {
+    let input = r#"PROCESSOR =~ /^PIC_1[68]F?$/ && PROCESSOR !~ /F$/"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("PROCESSOR".to_string(), "PIC_16".to_string());
+    assert!(parse_boolean_expression(input, &definitions).unwrap());
+    definitions.insert("PROCESSOR".to_string(), "PIC_16F".to_string());
+    assert!(!parse_boolean_expression(input, &definitions).unwrap());
+    definitions.insert("PROCESSOR".to_string(), "PIC_24".to_string());
+    assert!(!parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn regex_escaped_slash() {
+    let input = r#""dir/file.sinc" =~ /^dir\/[a-z]+\.sinc$/"#;
+    assert!(parse_boolean_expression(input, &Default::default()).unwrap());
+}
+
+#[test]
+fn regex_precedence() {
+    let input = r#"!(VERSION =~ /^2/) || VERSION + 1 =~ /^4$/"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("VERSION".to_string(), "3".to_string());
+    assert!(parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn regex_of_unary_operand() {
+    let mut definitions = HashMap::new();
+    definitions.insert("X".to_string(), "abc".to_string());
+    match Expr::parse("defined(X) && !X =~ /^a/") {
+        Err(Error::Parsing(message)) => {
+            assert!(message.contains("1:15"), "{}", message);
+            assert!(
+                message.contains("`!` applies before `=~`, write `!(X =~ /^a/)` or `(!X) =~ /^a/`"),
+                "{}",
+                message
+            );
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(matches!(Expr::parse("-X !~ /^a/"), Err(Error::Parsing(_))));
+    assert!(!parse_boolean_expression("!(X =~ /^a/)", &definitions).unwrap());
+    assert!(!parse_boolean_expression("X !~ /^a/", &definitions).unwrap());
+    let expr = Expr::parse("(!X) =~ /^a/").unwrap();
+    assert_eq!(expr.to_string(), "(!X) =~ /^a/");
+    assert!(matches!(expr.eval(&definitions), Err(Error::Type(_))));
+    let expr = Expr::parse("(-1) =~ /^-/").unwrap();
+    assert_eq!(expr.to_string(), "(-1) =~ /^-/");
+    assert!(expr.eval(&definitions).unwrap());
+}
+
+#[test]
+fn regex_compile_error() {
+    let input = r#"defined(X) && X =~ /PIC_(16/"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("X".to_string(), "PIC_16".to_string());
+    match parse_boolean_expression(input, &definitions) {
+        Err(Error::Parsing(message)) => {
+            assert!(message.contains("1:21"), "{}", message);
+            assert!(
+                message.contains("invalid regular expression"),
+                "{}",
+                message
+            );
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn builtin_functions() {
+    let input = r#"startswith(PROCESSOR, "ARM") && !contains(lower(PROCESSOR), "thumb")
+        && len(PROCESSOR) == 5 && int(VERSION) + 1 == 8 && endswith(upper("v8a"), "8A")"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("PROCESSOR".to_string(), "ARMv7".to_string());
+    definitions.insert("VERSION".to_string(), "7".to_string());
+    assert!(parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn exists_relative_to_directory() {
+    let resources = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let definitions = HashMap::new();
+    let functions = Functions::default();
+    let context = Context::new(&definitions, &functions).with_directory(Some(&resources));
+    assert!(evaluate_boolean_expression(r#"exists("includes/actual.inc")"#, &context).unwrap());
+    assert!(!evaluate_boolean_expression(r#"exists("includes/missing.inc")"#, &context).unwrap());
+}
+
+#[test]
+fn registered_function() {
+    let mut functions = Functions::default();
+    functions.register("is_little_endian", |args, context| {
+        assert!(args.is_empty());
+        let endian = context.definitions().get("ENDIAN").map(String::as_str);
+        Ok(Value::Bool(endian == Some("little")))
+    });
+    let mut definitions = HashMap::new();
+    definitions.insert("ENDIAN".to_string(), "little".to_string());
+    let context = Context::new(&definitions, &functions);
+    assert!(evaluate_boolean_expression("is_little_endian()", &context).unwrap());
+}
+
+#[test]
+fn unknown_function() {
+    let input = r#"startswith(PROCESSOR, "ARM") || is_arm(PROCESSOR)"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("PROCESSOR".to_string(), "ARMv7".to_string());
+    assert_eq!(
+        parse_boolean_expression(input, &definitions).err().unwrap(),
+        Error::UnknownFunction("is_arm".to_string())
+    );
+}
+
+#[test]
+fn function_arity() {
+    let input = r#"startswith(PROCESSOR)"#;
+    let mut definitions = HashMap::new();
+    definitions.insert("PROCESSOR".to_string(), "ARMv7".to_string());
+    assert_eq!(
+        parse_boolean_expression(input, &definitions).err().unwrap(),
+        Error::Type("startswith() takes 2 argument(s) but 1 were given".to_string())
+    );
+}
+
+#[test]
+fn string_escapes() {
+    let input = r#"QUOTED == "a\"b" && TAB == "\t" && OCTAL == "\101\60" && UNICODE == "é😀""#;
+    let mut definitions = HashMap::new();
+    definitions.insert("QUOTED".to_string(), "a\"b".to_string());
+    definitions.insert("TAB".to_string(), "\t".to_string());
+    definitions.insert("OCTAL".to_string(), "A0".to_string());
+    definitions.insert("UNICODE".to_string(), "é😀".to_string());
+    assert!(parse_boolean_expression(input, &definitions).unwrap());
+}
+
+#[test]
+fn invalid_escape() {
+    let input = r#""ab\qc" == X"#;
+    match parse_boolean_expression(input, &Default::default()) {
+        Err(Error::Parsing(message)) => {
+            assert!(message.contains("1:4"), "{}", message);
+            assert!(
+                message.contains("invalid escape sequence `\\q`"),
+                "{}",
+                message
+            );
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn expr_ast() {
+    let expr = Expr::parse(r#"defined(A) && B == "1""#).unwrap();
+    assert_eq!(
+        expr,
+        Expr::Binary(
+            BinaryOp::And,
+            Box::new(Expr::Defined("A".to_string())),
+            Box::new(Expr::Binary(
+                BinaryOp::Eq,
+                Box::new(Expr::Identifier("B".to_string())),
+                Box::new(Expr::String("1".to_string())),
+            )),
+        )
+    );
+}
+
+#[test]
+fn expr_eval_many() {
+    let expr: Expr = r#"PROCESSOR =~ /^PIC_1[68]/ || defined(PIC_ALL)"#.parse().unwrap();
+    let variants = [
+        ("PIC_16", false, true),
+        ("PIC_24", false, false),
+        ("PIC_24", true, true),
+    ];
+    for (processor, all, expected) in variants.iter() {
+        let mut definitions = HashMap::new();
+        definitions.insert("PROCESSOR".to_string(), processor.to_string());
+        if *all {
+            definitions.insert("PIC_ALL".to_string(), String::new());
+        }
+        assert_eq!(expr.eval(&definitions).unwrap(), *expected);
+    }
+}
+
+#[test]
+fn expr_referenced_identifiers() {
+    let expr = Expr::parse(r#"!(A!="A" || defined(B)) && startswith(C, "x") && "D" == A"#).unwrap();
+    assert_eq!(
+        expr.referenced_identifiers()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec!["A", "B", "C"]
+    );
+}
+
+#[test]
+fn expr_display() {
+    let cases = [
+        (r#"((A == "1"))"#, r#"A == "1""#),
+        (
+            r#"!(A!="A" || B=="B" ^^ (C!="C" || D=="D") && E!="E")"#,
+            r#"!(A != "A" || B == "B" ^^ (C != "C" || D == "D") && E != "E")"#,
+        ),
+        ("(1 + 2) * 3 - (4 - 5) - -6", "(1 + 2) * 3 - (4 - 5) - -6"),
+        ("(FEATURES & 0x4) != 0", "(FEATURES & 4) != 0"),
+        (
+            r#"!defined(X)&&lower( P )=~/^a\/b/"#,
+            r#"!defined(X) && lower(P) =~ /^a\/b/"#,
+        ),
+        (r#"X == "a\"b\t\u00e9""#, r#"X == "a\"b\té""#),
+    ];
+    for (input, expected) in cases.iter() {
+        let expr = Expr::parse(input).unwrap();
+        let printed = expr.to_string();
+        assert_eq!(&printed, expected);
+        assert_eq!(Expr::parse(&printed).unwrap(), expr);
+    }
+}
+
+#[test]
+fn expr_display_negative_literals() {
+    for expr in [
+        Expr::Integer(-6),
+        Expr::Binary(
+            BinaryOp::Sub,
+            Box::new(Expr::Identifier("X".into())),
+            Box::new(Expr::Integer(-6)),
+        ),
+    ] {
+        assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+    }
+    assert_eq!(Expr::parse("-(6)").unwrap(), Expr::Integer(-6));
+    assert_eq!(Expr::parse("- -6").unwrap(), Expr::Integer(6));
+}
+
+#[test]
+fn expr_parse_errors_before_evaluation() {
+    assert!(matches!(
+        Expr::parse(r#"defined(X) && X =~ /PIC_(16/"#),
+        Err(Error::Parsing(_))
+    ));
+    assert!(matches!(
+        Expr::parse("X == 99999999999999999999"),
+        Err(Error::Parsing(_))
+    ));
+}
+
+fn simplify(input: &str, known: &KnownDefinitions) -> String {
+    Expr::parse(input).unwrap().simplify(known).to_string()
+}
+
+#[test]
+fn simplify_defined() {
+    let input = r#"defined(A) && B == "1""#;
+    let mut known = KnownDefinitions::new();
+    assert_eq!(simplify(input, &known), input);
+    known.define("A", "");
+    assert_eq!(simplify(input, &known), r#"B == "1""#);
+    known.undefine("A");
+    assert_eq!(simplify(input, &known), "0");
+    assert_eq!(
+        Expr::parse(input).unwrap().simplify(&known).as_condition(),
+        Some(false)
+    );
+}
+
+#[test]
+fn simplify_values() {
+    let mut known = KnownDefinitions::new();
+    known.define("VERSION", "2");
+    known.define("PROCESSOR", "PIC_16F");
+    assert_eq!(simplify(r#"VERSION == "2" || X"#, &known), "1");
+    assert_eq!(
+        simplify(r#"VERSION == "3" || X == "1""#, &known),
+        r#"X == "1""#
+    );
+    assert_eq!(simplify(r#"VERSION + 1 == X"#, &known), "3 == X");
+    assert_eq!(
+        simplify(r#"PROCESSOR =~ /F$/ ^^ defined(Y)"#, &known),
+        "!defined(Y)"
+    );
+    assert_eq!(
+        simplify(r#"startswith(PROCESSOR, "PIC") && defined(Y)"#, &known),
+        "defined(Y)"
+    );
+    assert_eq!(
+        simplify(r#"exists(PROCESSOR)"#, &known),
+        r#"exists("PIC_16F")"#
+    );
+}
+
+#[test]
+fn simplify_keeps_non_boolean_operands() {
+    let mut known = KnownDefinitions::new();
+    known.define("A", "");
+    assert_eq!(
+        simplify("defined(A) && FEATURES & 4", &known),
+        "1 && FEATURES & 4"
+    );
+    assert_eq!(simplify("!!defined(B) || 0", &known), "defined(B)");
+}
+
+#[test]
+fn simplify_keeps_errors() {
+    let known = KnownDefinitions::from(HashMap::new());
+    assert_eq!(simplify("1 / 0 == 0", &known), "1 / 0 == 0");
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use sleigh_preprocessor::boolean_expression::Value;
+use sleigh_preprocessor::cache::IncludeCache;
+use sleigh_preprocessor::{Definitions, SleighPreprocessor};
+
+type Run = (
+    String,
+    Definitions,
+    Vec<(PathBuf, usize, usize)>,
+    Vec<String>,
+);
+
+fn run(name: &str, cache: Option<&IncludeCache>) -> Run {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("resources/cache")
+        .join(name);
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), path, false);
+    if let Some(cache) = cache {
+        preprocessor = preprocessor.with_include_cache(cache.clone());
+    }
+    let mut writer = String::new();
+    preprocessor.process(&mut writer).unwrap();
+    let locations = preprocessor
+        .locations()
+        .iter()
+        .map(|l| {
+            let file_name = PathBuf::from(l.filepath().file_name().unwrap());
+            (file_name, l.local_line_num(), l.global_line_num())
+        })
+        .collect();
+    let diagnostics = preprocessor
+        .diagnostics()
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+    (
+        writer,
+        preprocessor.take_definitions(),
+        locations,
+        diagnostics,
+    )
+}
+
+#[test]
+fn cached_runs_match_uncached_runs() {
+    let cache = IncludeCache::new();
+    let mut reused_outputs = Vec::new();
+    for name in &["a.slaspec", "b.slaspec", "c.slaspec", "a.slaspec"] {
+        assert_eq!(run(name, Some(&cache)), run(name, None), "{}", name);
+        reused_outputs.push(cache.reused_outputs());
+    }
+    // `nested.sinc` only depends on SIZE and `common.sinc` also on ENDIAN_BIG, while the
+    // variants differ by PROCESSOR and, for `c.slaspec`, by SIZE.
+    assert_eq!(reused_outputs, [1, 3, 4, 6]);
+}
+
+#[test]
+fn included_changes_are_replayed() {
+    let cache = IncludeCache::new();
+    run("a.slaspec", Some(&cache));
+    let (output, definitions, _, diagnostics) = run("b.slaspec", Some(&cache));
+    assert!(output.contains(":nop is op=0 { }"));
+    assert_eq!(definitions.get("COMMON_SEEN").map(String::as_str), Some(""));
+    assert_eq!(diagnostics.len(), 2);
+}
+
+#[test]
+fn custom_functions_disable_reuse() {
+    let cache = IncludeCache::new();
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/cache/a.slaspec");
+    for _ in 0..2 {
+        let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &path, false)
+            .with_function("always", |_, _| Ok(Value::Bool(true)))
+            .with_include_cache(cache.clone());
+        preprocessor.process(&mut String::new()).unwrap();
+    }
+    assert_eq!(cache.reused_outputs(), 0);
+}
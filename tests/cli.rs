@@ -0,0 +1,127 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use common::resources;
+use sleigh_preprocessor::SleighPreprocessor;
+
+/// Empty directory for the outputs of a test.
+fn output_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn preprocess_dir(output: &Path, dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sleigh_preprocessor"))
+        .arg("dir")
+        .arg("-o")
+        .arg(output)
+        .arg(dir)
+        .output()
+        .unwrap()
+}
+
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn dir_mirrors_the_tree() {
+    let input = resources("cache");
+    let output = output_dir("dir_mirrors_the_tree");
+    let result = preprocess_dir(&output, &input);
+    assert!(result.status.success());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    let summary = stdout.lines().last().unwrap();
+    assert!(
+        summary.starts_with("3 succeeded, 0 failed in "),
+        "{}",
+        summary
+    );
+    assert_eq!(file_names(&output), ["a.slaspec", "b.slaspec", "c.slaspec"]);
+    for name in file_names(&output) {
+        let mut expected = String::new();
+        SleighPreprocessor::new(HashMap::new(), input.join(&name), true)
+            .process(&mut expected)
+            .unwrap();
+        let actual = std::fs::read_to_string(output.join(&name)).unwrap();
+        assert_eq!(actual, expected, "{}", name);
+    }
+}
+
+#[test]
+fn dir_reports_failures() {
+    let output = output_dir("dir_reports_failures");
+    let result = preprocess_dir(&output, &resources("specialize/error"));
+    assert_eq!(result.status.code(), Some(1));
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout
+        .lines()
+        .last()
+        .unwrap()
+        .starts_with("0 succeeded, 1 failed in "));
+    assert!(file_names(&output).is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn dir_skips_linked_directories() {
+    let input = output_dir("dir_skips_linked_directories");
+    for name in &["a.slaspec", "common.sinc", "nested.sinc"] {
+        std::fs::copy(resources("cache").join(name), input.join(name)).unwrap();
+    }
+    std::os::unix::fs::symlink(&input, input.join("loop")).unwrap();
+    let output = input.join("out");
+    let result = preprocess_dir(&output, &input);
+    assert!(result.status.success());
+    assert_eq!(file_names(&output), ["a.slaspec"]);
+}
+
+#[test]
+fn gcc_style_options_before_arguments_only() {
+    let dir = output_dir("gcc_style_options_before_arguments_only");
+    std::fs::write(dir.join("-MTx.slaspec"), "x\n").unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_sleigh_preprocessor"))
+        .current_dir(&dir)
+        .args(["-M", "-MTx.sla", "-MFx.d", "--", "-MTx.slaspec"])
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("x.d")).unwrap(),
+        "x.sla: \\\n  -MTx.slaspec\n"
+    );
+}
+
+#[test]
+fn indented_directives_as_text() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sleigh_preprocessor"))
+        .args(["--no-indented-directives", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"  @ifdef A\nx\n  @endif\n")
+        .unwrap();
+    let result = child.wait_with_output().unwrap();
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap(),
+        "  @ifdef A\nx\n  @endif\n"
+    );
+}
@@ -0,0 +1,8 @@
+use std::path::PathBuf;
+
+/// Path of `path` in the resources of the tests.
+pub fn resources(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("resources")
+        .join(path)
+}
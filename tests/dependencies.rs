@@ -0,0 +1,105 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use common::resources;
+use sleigh_preprocessor::cache::IncludeCache;
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn file_names(paths: &[PathBuf]) -> Vec<&str> {
+    paths
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn included_files() {
+    let mut definitions = HashMap::new();
+    definitions.insert("REPLACE".into(), "includes".into());
+    let mut preprocessor = SleighPreprocessor::new(definitions, resources("include.input"), true);
+    preprocessor.process(&mut String::new()).unwrap();
+    let dependencies = preprocessor.dependencies();
+    assert_eq!(
+        file_names(dependencies.files()),
+        ["include.input", "empty.input", "crazy.inc", "actual.inc"]
+    );
+    assert!(dependencies.missing().is_empty());
+}
+
+#[test]
+fn missing_files_looked_up() {
+    let mut preprocessor =
+        SleighPreprocessor::new(HashMap::new(), resources("include_dir.input"), true)
+            .with_include_dir(resources("specialize"))
+            .with_include_dir(resources("includes"));
+    preprocessor.process(&mut String::new()).unwrap();
+    let dependencies = preprocessor.dependencies();
+    assert_eq!(
+        dependencies.files(),
+        [
+            resources("include_dir.input"),
+            resources("includes/actual.inc")
+        ]
+    );
+    assert_eq!(
+        dependencies.missing(),
+        [resources("actual.inc"), resources("specialize/actual.inc")]
+    );
+}
+
+#[test]
+fn reused_outputs_keep_dependencies() {
+    let cache = IncludeCache::new();
+    let mut files = Vec::new();
+    for name in &["a.slaspec", "b.slaspec"] {
+        let path = resources("cache").join(name);
+        let mut preprocessor =
+            SleighPreprocessor::new(HashMap::new(), path, false).with_include_cache(cache.clone());
+        preprocessor.process(&mut String::new()).unwrap();
+        files.push(preprocessor.dependencies().files().to_vec());
+    }
+    assert_eq!(cache.reused_outputs(), 3);
+    assert_eq!(
+        file_names(&files[0]),
+        ["a.slaspec", "common.sinc", "nested.sinc"]
+    );
+    assert_eq!(
+        file_names(&files[1]),
+        ["b.slaspec", "common.sinc", "nested.sinc"]
+    );
+}
+
+#[test]
+fn makefile_rule() {
+    let mut preprocessor =
+        SleighPreprocessor::new(HashMap::new(), Path::new("dir #1/x.slaspec"), true)
+            .with_source("@include \"actual.inc\"\n")
+            .with_include_dir(resources("includes"));
+    preprocessor.process(&mut String::new()).unwrap();
+    let actual = resources("includes/actual.inc");
+    assert_eq!(
+        preprocessor.dependencies().to_makefile("$x.sla"),
+        format!(
+            "$$x.sla: \\\n  dir\\ \\#1/x.slaspec \\\n  {0} \\\n  dir\\ \\#1/actual.inc\n\n{0}:\n\n\
+             dir\\ \\#1/actual.inc:\n",
+            actual.display()
+        )
+    );
+}
+
+#[test]
+fn makefile_rule_without_phony_root() {
+    let dir = resources("cache");
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), dir.join("a.slaspec"), true);
+    preprocessor.process(&mut String::new()).unwrap();
+    assert_eq!(
+        preprocessor.dependencies().to_makefile("a.sla"),
+        format!(
+            "a.sla: \\\n  {0}/a.slaspec \\\n  {0}/common.sinc \\\n  {0}/nested.sinc\n\n\
+             {0}/common.sinc:\n\n{0}/nested.sinc:\n",
+            dir.display()
+        )
+    );
+}
@@ -0,0 +1,53 @@
+use sleigh_preprocessor::escape::unescape;
+
+#[test]
+fn simple_escapes() {
+    assert_eq!(
+        unescape(r#"\b\t\n\f\r\"\'\\"#).unwrap(),
+        "\u{8}\t\n\u{c}\r\"'\\"
+    );
+}
+
+#[test]
+fn octal_escapes() {
+    assert_eq!(
+        unescape(r"\0\12\101\377\400").unwrap(),
+        "\0\nA\u{ff}\u{20}0"
+    );
+}
+
+#[test]
+fn unicode_escapes() {
+    assert_eq!(unescape(r"Aé😀").unwrap(), "Aé😀");
+}
+
+#[test]
+fn unknown_escape() {
+    let error = unescape(r"abc\qdef").unwrap_err();
+    assert_eq!((error.start(), error.end()), (3, 5));
+    assert_eq!(error.to_string(), "invalid escape sequence `\\q`");
+}
+
+#[test]
+fn truncated_escapes() {
+    assert_eq!(
+        unescape(r"abc\").unwrap_err().to_string(),
+        "invalid escape sequence `\\`"
+    );
+    assert_eq!(
+        unescape(r"\u12").unwrap_err().to_string(),
+        "invalid escape sequence `\\u12`"
+    );
+}
+
+#[test]
+fn lone_surrogate() {
+    assert_eq!(
+        unescape(r"\uD83Dx").unwrap_err().to_string(),
+        "invalid escape sequence `\\uD83D`"
+    );
+    assert_eq!(
+        unescape(r"\uDE00").unwrap_err().to_string(),
+        "invalid escape sequence `\\uDE00`"
+    );
+}
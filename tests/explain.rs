@@ -0,0 +1,95 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use common::resources;
+use sleigh_preprocessor::cache::IncludeCache;
+use sleigh_preprocessor::explain::Branch;
+use sleigh_preprocessor::SleighPreprocessor;
+
+const ROOT: &str = r#"@define B "1"
+@ifdef A
+@elif B == "1"
+@if defined(C) || B == "2"
+@include "leaf.sinc"
+@else
+@include "leaf.sinc"
+@endif
+@endif
+"#;
+
+fn explain(path: PathBuf, line_no: usize) -> SleighPreprocessor {
+    let mut preprocessor =
+        SleighPreprocessor::new(HashMap::new(), resources("explain/root.slaspec"), true)
+            .with_source(ROOT)
+            .with_explain(path, line_no)
+            .with_include_cache(IncludeCache::new());
+    preprocessor.process(&mut String::new()).unwrap();
+    preprocessor
+}
+
+#[test]
+fn inactive_line() {
+    let root = resources("explain/root.slaspec");
+    let preprocessor = explain(root.clone(), 5);
+    let explanations = preprocessor.explanations();
+    assert_eq!(explanations.len(), 1);
+    let explanation = &explanations[0];
+    assert!(!explanation.active);
+    assert_eq!(explanation.conditionals.len(), 2);
+    assert_eq!(explanation.conditionals[0].path, root);
+    assert_eq!(
+        explanation.conditionals[0].branches,
+        [
+            Branch {
+                line_no: 2,
+                directive: "@ifdef A".into(),
+                value: Some(false),
+                taken: false,
+                definitions: vec![("A".into(), None)],
+            },
+            Branch {
+                line_no: 3,
+                directive: "@elif B == \"1\"".into(),
+                value: Some(true),
+                taken: true,
+                definitions: vec![("B".into(), Some("1".into()))],
+            },
+        ]
+    );
+    assert_eq!(
+        explanation.conditionals[1].branches,
+        [Branch {
+            line_no: 4,
+            directive: "@if defined(C) || B == \"2\"".into(),
+            value: Some(false),
+            taken: false,
+            definitions: vec![("B".into(), Some("1".into())), ("C".into(), None)],
+        }]
+    );
+}
+
+#[test]
+fn included_line() {
+    let preprocessor = explain(resources("explain/leaf.sinc"), 3);
+    let explanations = preprocessor.explanations();
+    assert_eq!(explanations.len(), 1);
+    assert!(explanations[0].active);
+    assert_eq!(explanations[0].includes[0].line_no, 7);
+    assert_eq!(
+        explanations[0].to_string(),
+        format!(
+            "{leaf}:3 is active
+  in file included from {root}:7
+  {root}:2: @ifdef A = false, not taken (A undefined)
+  {root}:3: @elif B == \"1\" = true, taken (B = \"1\")
+    {root}:4: @if defined(C) || B == \"2\" = false, not taken (B = \"1\", C undefined)
+    {root}:6: @else, taken
+      {leaf}:2: @ifndef D = true, taken (D undefined)
+",
+            root = resources("explain/root.slaspec").display(),
+            leaf = resources("explain/leaf.sinc").display(),
+        )
+    );
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use sleigh_preprocessor::format::Formatter;
+use sleigh_preprocessor::SleighPreprocessor;
+
+#[test]
+fn format_directives() {
+    let source = "\
+@define   A   a\\b
+@define B \"b\"    # quoted
+@define C
+@ifdef   A
+define token t (8) f = (0, 7);   # body text is untouched
+@if B == \"b\" &&   defined(C)
+@include   \"file.sinc\"
+@elif 1
+@else   # else
+@endif
+@endif\t\r
+";
+    let formatted = "\
+@define A a\\b
+@define B \"b\" # quoted
+@define C
+@ifdef A
+define token t (8) f = (0, 7);   # body text is untouched
+@if B == \"b\" &&   defined(C)
+@include \"file.sinc\"
+@elif 1
+@else # else
+@endif
+@endif\r
+";
+    let formatter = Formatter::new();
+    assert_eq!(formatter.format(source), formatted);
+    assert!(!formatter.is_formatted(source));
+    assert!(formatter.is_formatted(formatted));
+}
+
+#[test]
+fn format_indent() {
+    let source = "\
+@ifdef A
+@if B
+b
+        @elif C
+@define D
+   @endif
+\t@else
+@undef A
+@endif
+";
+    let formatted = "\
+@ifdef A
+  @if B
+b
+  @elif C
+    @define D
+  @endif
+@else
+  @undef A
+@endif
+";
+    let formatter = Formatter::new().with_indent(2);
+    assert_eq!(formatter.format(source), formatted);
+    assert!(formatter.is_formatted(formatted));
+    assert_eq!(
+        Formatter::new().format(formatted),
+        "@ifdef A\n@if B\nb\n@elif C\n@define D\n@endif\n@else\n@undef A\n@endif\n"
+    );
+}
+
+#[test]
+fn format_leaves_unknown_lines() {
+    let source = "@unknown   x\n@include   file.sinc\n@endif  \n# @define  A\n";
+    assert_eq!(
+        Formatter::new().format(source),
+        "@unknown   x\n@include   file.sinc\n@endif\n# @define  A\n"
+    );
+}
+
+#[test]
+fn format_keeps_define_values() {
+    let definitions = |source: &str| {
+        let mut preprocessor =
+            SleighPreprocessor::new(HashMap::new(), "virtual.input", true).with_source(source);
+        preprocessor.process(&mut String::new()).unwrap();
+        preprocessor.take_definitions()
+    };
+    let source = "@define X a\\b\n@define Y a\"b\n@define Z ab\n";
+    let formatted = Formatter::new().format(source);
+    assert_eq!(
+        formatted,
+        "@define X a\\b\n@define Y a\"b\n@define Z \"ab\"\n"
+    );
+    assert_eq!(definitions(&formatted), definitions(source));
+    assert_eq!(definitions(&formatted)["X"], "a\\b");
+}
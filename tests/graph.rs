@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use sleigh_preprocessor::graph::IncludeEdge;
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn preprocess_source(source: &str) -> SleighPreprocessor {
+    let path = PathBuf::from("graph.input");
+    let mut definitions = HashMap::new();
+    definitions.insert("DIR".into(), "dir".into());
+    let mut preprocessor = SleighPreprocessor::new(definitions, path, true).with_source(source);
+    preprocessor.process(&mut String::new()).unwrap();
+    preprocessor
+}
+
+#[test]
+fn active_and_inactive_includes() {
+    let resources = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let root = resources.join("graph.input");
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true);
+    preprocessor.process(&mut String::new()).unwrap();
+    let graph = preprocessor.include_graph();
+    assert_eq!(
+        graph.edges(),
+        [
+            IncludeEdge {
+                from: root.clone(),
+                to: resources.join("$(ARCH)/never.sinc"),
+                line_no: 2,
+                active: false,
+            },
+            IncludeEdge {
+                from: root.clone(),
+                to: resources.join("empty.input"),
+                line_no: 4,
+                active: true,
+            },
+        ]
+    );
+    assert_eq!(
+        graph.files(),
+        [
+            root.as_path(),
+            &resources.join("$(ARCH)/never.sinc"),
+            &resources.join("empty.input")
+        ]
+    );
+}
+
+#[test]
+fn inactive_include_of_root_without_directory() {
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), "/", true)
+        .with_source("@ifdef ARCH\n@include \"arch.sinc\"\n@endif\n");
+    preprocessor.process(&mut String::new()).unwrap();
+    assert_eq!(
+        preprocessor.include_graph().edges(),
+        [IncludeEdge {
+            from: PathBuf::from("/"),
+            to: PathBuf::from("arch.sinc"),
+            line_no: 2,
+            active: false,
+        }]
+    );
+}
+
+#[test]
+fn export() {
+    let preprocessor = preprocess_source("@if 0\n@include \"$(DIR)/a \"b\".sinc\"\n@endif\n");
+    let graph = preprocessor.include_graph();
+    assert_eq!(
+        graph.to_dot(),
+        r#"digraph includes {
+  "graph.input";
+  "dir/a \"b\".sinc";
+  "graph.input" -> "dir/a \"b\".sinc" [label="2", style=dashed];
+}
+"#
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn export_json() {
+    let preprocessor = preprocess_source("@if 0\n@include \"$(DIR)/a \"b\".sinc\"\n@endif\n");
+    assert_eq!(
+        preprocessor.include_graph().to_json(),
+        r#"{"files":["graph.input","dir/a \"b\".sinc"],"edges":[{"from":"graph.input","to":"dir/a \"b\".sinc","line":2,"active":false}]}
+"#
+    );
+}
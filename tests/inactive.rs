@@ -0,0 +1,64 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use common::resources;
+use sleigh_preprocessor::cache::IncludeCache;
+use sleigh_preprocessor::inactive::InactiveRegion;
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn region(path: &Path, start: usize, end: usize, conditional: usize) -> InactiveRegion {
+    InactiveRegion {
+        path: path.to_path_buf(),
+        start_line_no: start,
+        end_line_no: end,
+        conditional_line_no: conditional,
+    }
+}
+
+#[test]
+fn nested_branches() {
+    let root = resources("inactive/root.slaspec");
+    let source = "a\n@if 0\nb\n@if 1\nc\n@endif\nd\n@elif 1\ne\n@else\nf\n@endif\n";
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true).with_source(source);
+    preprocessor.process(&mut String::new()).unwrap();
+    assert_eq!(
+        preprocessor.inactive_regions().regions(),
+        [region(&root, 3, 7, 2), region(&root, 11, 11, 10)]
+    );
+}
+
+#[test]
+fn branches_after_taken_branch() {
+    let root = resources("inactive/root.slaspec");
+    let source = "@if 1\na\n@elif 1\nb\n@else\nc\n@endif\n@if 0\nd\n@elif 0\ne\n@endif\n";
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true).with_source(source);
+    preprocessor.process(&mut String::new()).unwrap();
+    assert_eq!(
+        preprocessor.inactive_regions().regions(),
+        [
+            region(&root, 4, 4, 3),
+            region(&root, 6, 6, 5),
+            region(&root, 9, 9, 8),
+            region(&root, 11, 11, 10),
+        ]
+    );
+}
+
+#[test]
+fn included_files() {
+    let root = resources("inactive/root.slaspec");
+    let part = resources("inactive/part.sinc");
+    let source = "@include \"part.sinc\"\n@ifdef Y\ny\n@endif\n@include \"part.sinc\"\n";
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true)
+        .with_source(source)
+        .with_include_cache(IncludeCache::new());
+    preprocessor.process(&mut String::new()).unwrap();
+    let regions = preprocessor.inactive_regions();
+    assert_eq!(
+        regions.regions(),
+        [region(&part, 2, 2, 1), region(&root, 3, 3, 2)]
+    );
+    assert_eq!(regions.in_file(&part), [&region(&part, 2, 2, 1)]);
+}
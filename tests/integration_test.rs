@@ -1,6 +1,8 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use sleigh_preprocessor::boolean_expression::Value;
+use sleigh_preprocessor::diagnostic::Severity;
 use sleigh_preprocessor::SleighPreprocessor;
 
 fn common(input_name: &str) -> String {
@@ -83,3 +85,218 @@ fn regression_oneline_define() {
     let output = include_str!("../resources/oneline_define.output");
     assert_eq!(output, writer);
 }
+
+#[test]
+fn arithmetic() {
+    let writer = common("arithmetic");
+    let output = include_str!("../resources/arithmetic.output");
+    assert_eq!(output, writer);
+}
+
+#[test]
+fn functions() {
+    let mut writer = String::new();
+    let mut definitions = HashMap::new();
+    definitions.insert("REPLACE".into(), "includes".into());
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("resources/functions.input");
+    let mut sleigh_preprocessor = SleighPreprocessor::new(definitions, path, false)
+        .with_function("is_thumb", |args, _| {
+            Ok(Value::Bool(args[0].as_string()?.ends_with("v7")))
+        });
+    sleigh_preprocessor.process(&mut writer).unwrap();
+    let output = include_str!("../resources/functions.output");
+    assert_eq!(output, writer);
+}
+
+#[test]
+fn escapes() {
+    let writer = common("escapes");
+    let output = include_str!("../resources/escapes.output");
+    assert_eq!(output, writer);
+}
+
+#[test]
+fn invalid_escape() {
+    let mut writer = String::new();
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("resources/invalid_escape.input");
+    let mut sleigh_preprocessor = SleighPreprocessor::new(HashMap::new(), path, false);
+    let error = sleigh_preprocessor.process(&mut writer).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Preprocessor error: invalid escape sequence `\\q` at invalid_escape.input:2(2): \
+         @define BAD \"oops\\q\""
+    );
+}
+
+#[test]
+fn indented() {
+    let mut writer = String::new();
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("resources/indented.input");
+    let mut sleigh_preprocessor = SleighPreprocessor::new(HashMap::new(), path, false);
+    sleigh_preprocessor.process(&mut writer).unwrap();
+    let output = include_str!("../resources/indented.output");
+    assert_eq!(output, writer);
+    let diagnostics: Vec<_> = sleigh_preprocessor
+        .diagnostics()
+        .iter()
+        .map(|d| (d.severity(), d.line_no(), d.message()))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![(
+            Severity::Warning,
+            13,
+            "unrecognized indented preprocessor directive copied as text"
+        )]
+    );
+}
+
+#[test]
+fn indented_ghidra_compatible() {
+    let mut writer = String::new();
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("resources/indented.input");
+    let mut sleigh_preprocessor =
+        SleighPreprocessor::new(HashMap::new(), path, true).with_indented_directives(false);
+    // the indented @define is ignored
+    let error = sleigh_preprocessor.process(&mut writer).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Preprocessor error: unknown variable: C at indented.input:10(10): \tc is $(C)"
+    );
+    assert!(writer.contains("\n    not here\n"));
+    let lines: Vec<_> = sleigh_preprocessor
+        .diagnostics()
+        .iter()
+        .map(|d| d.line_no())
+        .collect();
+    assert_eq!(lines, vec![3, 5, 7, 8, 9]);
+    assert_eq!(
+        sleigh_preprocessor.diagnostics()[0].message(),
+        "indented preprocessor directive copied as text"
+    );
+}
+
+#[test]
+fn hash_in_quotes() {
+    let writer = common("hash_in_quotes");
+    let output = include_str!("../resources/hash_in_quotes.output");
+    assert_eq!(output, writer);
+}
+
+#[test]
+fn include_locations() {
+    let mut writer = String::new();
+    let mut definitions = HashMap::new();
+    definitions.insert("REPLACE".into(), "includes".into());
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/include.input");
+    let mut sleigh_preprocessor = SleighPreprocessor::new(definitions, path, true);
+    sleigh_preprocessor.process(&mut writer).unwrap();
+    let locations: Vec<_> = sleigh_preprocessor
+        .locations()
+        .iter()
+        .map(|l| {
+            let file_name = l.filepath().file_name().unwrap().to_str().unwrap();
+            (file_name, l.local_line_num(), l.global_line_num())
+        })
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            ("include.input", 1, 1),
+            ("empty.input", 1, 2),
+            ("include.input", 3, 5),
+            ("crazy.inc", 1, 7),
+            ("empty.input", 1, 9),
+            ("crazy.inc", 4, 12),
+            ("include.input", 6, 14),
+            ("empty.input", 1, 17),
+            ("include.input", 10, 20),
+            ("actual.inc", 1, 21),
+            ("include.input", 12, 22),
+        ]
+    );
+    let lines: Vec<_> = writer.lines().collect();
+    assert_eq!(lines[7 - 1], "print foo");
+    assert_eq!(lines[21 - 1], "Hey, you found me!");
+}
+
+#[test]
+fn include_dirs() {
+    let resources = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let path = resources.join("include_dir.input");
+    let mut writer = String::new();
+    let mut sleigh_preprocessor = SleighPreprocessor::new(HashMap::new(), &path, true)
+        .with_include_dir(resources.join("specialize"))
+        .with_include_dir(resources.join("includes"));
+    sleigh_preprocessor.process(&mut writer).unwrap();
+    assert!(writer.starts_with("Hey, you found me!\n"));
+    assert_eq!(
+        sleigh_preprocessor.locations()[1].filepath(),
+        resources.join("includes/actual.inc")
+    );
+
+    let mut sleigh_preprocessor = SleighPreprocessor::new(HashMap::new(), &path, true);
+    let error = sleigh_preprocessor.process(&mut String::new()).unwrap_err();
+    assert!(error.to_string().contains("actual.inc\" does not exist"));
+}
+
+#[test]
+fn in_memory_source() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/virtual.input");
+    let mut writer = String::new();
+    let mut definitions = HashMap::new();
+    definitions.insert("REPLACE".into(), "includes".into());
+    let mut sleigh_preprocessor = SleighPreprocessor::new(definitions, &path, false)
+        .with_source("@include \"$(REPLACE)/actual.inc\"\r\ntext\n");
+    sleigh_preprocessor.process(&mut writer).unwrap();
+    assert_eq!(
+        writer,
+        "\x08virtual.input###1\x08\x08actual.inc###1\x08Hey, you found me!\n\
+         \x08virtual.input###2\x08text\n"
+    );
+    assert_eq!(sleigh_preprocessor.locations()[0].filepath(), path);
+}
+
+#[test]
+fn source_named_after_root_directory() {
+    let mut writer = String::new();
+    let mut sleigh_preprocessor = SleighPreprocessor::new(HashMap::new(), "/", false).with_source(
+        "@include \"resources/includes/actual.inc\"\n\
+             @if exists(\"resources/includes/actual.inc\")\nfound\n@endif\n",
+    );
+    sleigh_preprocessor.process(&mut writer).unwrap();
+    assert!(writer.contains("Hey, you found me!"));
+    assert!(writer.contains("\nfound\n"));
+    assert_eq!(
+        sleigh_preprocessor.dependencies().files(),
+        [Path::new("/"), Path::new("resources/includes/actual.inc")]
+    );
+}
+
+#[test]
+fn process_twice() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/virtual.input");
+    let mut definitions = HashMap::new();
+    definitions.insert("REPLACE".into(), "includes".into());
+    let mut sleigh_preprocessor = SleighPreprocessor::new(definitions, &path, true)
+        .with_source("@include \"$(REPLACE)/actual.inc\"\n@ifdef X\n  @pragma\n@endif\n");
+    let mut first = String::new();
+    sleigh_preprocessor.process(&mut first).unwrap();
+    let locations = sleigh_preprocessor.locations().len();
+    let diagnostics = sleigh_preprocessor.diagnostics().to_vec();
+    let dependencies = sleigh_preprocessor.dependencies().clone();
+    let include_graph = sleigh_preprocessor.include_graph().clone();
+    let inactive_regions = sleigh_preprocessor.inactive_regions().clone();
+    let mut second = String::new();
+    sleigh_preprocessor.process(&mut second).unwrap();
+    assert_eq!(second, first);
+    assert_eq!(sleigh_preprocessor.locations().len(), locations);
+    assert_eq!(sleigh_preprocessor.diagnostics(), diagnostics);
+    assert_eq!(sleigh_preprocessor.dependencies(), &dependencies);
+    assert_eq!(sleigh_preprocessor.include_graph(), &include_graph);
+    assert_eq!(sleigh_preprocessor.inactive_regions(), &inactive_regions);
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use common::resources;
+use sleigh_preprocessor::cache::IncludeCache;
+use sleigh_preprocessor::diagnostic::DiagnosticKind;
+use sleigh_preprocessor::errors::Error;
+use sleigh_preprocessor::location::IncludeSite;
+use sleigh_preprocessor::sarif::SarifLog;
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn site(path: PathBuf, line_no: usize) -> IncludeSite {
+    IncludeSite { path, line_no }
+}
+
+#[test]
+fn include_chains() {
+    let root = resources("sarif/root.slaspec");
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true)
+        .with_source("@include \"outer.sinc\"\n");
+    let error = preprocessor.process(&mut String::new()).unwrap_err();
+    let includes = [site(root, 1), site(resources("sarif/outer.sinc"), 2)];
+    let diagnostic = &preprocessor.diagnostics()[0];
+    assert_eq!(diagnostic.kind(), DiagnosticKind::UnrecognizedDirective);
+    assert_eq!(diagnostic.includes(), includes);
+    let error = match error {
+        Error::Preprocessor(e) => e,
+        e => panic!("unexpected error: {}", e),
+    };
+    assert_eq!(
+        error.file_path(),
+        Some(resources("sarif/nested.sinc").as_path())
+    );
+    assert_eq!(error.line_no(), 2);
+    assert_eq!(error.column(), Some(5));
+    assert_eq!(error.includes(), includes);
+}
+
+#[test]
+fn include_chains_of_reused_outputs() {
+    let root = resources("sarif/root.slaspec");
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true)
+        .with_source("@include \"warning.sinc\"\n\n@include \"warning.sinc\"\n")
+        .with_include_cache(IncludeCache::new());
+    preprocessor.process(&mut String::new()).unwrap();
+    let includes: Vec<_> = preprocessor
+        .diagnostics()
+        .iter()
+        .map(|d| d.includes().to_vec())
+        .collect();
+    assert_eq!(includes, [vec![site(root.clone(), 1)], vec![site(root, 3)]]);
+}
+
+#[test]
+fn sarif_log() {
+    let root = resources("sarif/root.slaspec");
+    let mut preprocessor = SleighPreprocessor::new(HashMap::new(), &root, true)
+        .with_source("@include \"outer.sinc\"\n");
+    let error = preprocessor.process(&mut String::new()).unwrap_err();
+    let mut log = SarifLog::new();
+    for diagnostic in preprocessor.diagnostics() {
+        log.add_diagnostic(diagnostic);
+    }
+    log.add_error(&error);
+    log.add_error(&Error::NotDefined("A".into()));
+    let log: Value = serde_json::from_str(&log.to_json()).unwrap();
+    assert_eq!(log["version"], "2.1.0");
+    let run = &log["runs"][0];
+    let rules: Vec<_> = run["tool"]["driver"]["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rule| rule["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(rules, ["unrecognized_directive", "parsing", "not_defined"]);
+
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(results[0]["level"], "warning");
+    assert_eq!(results[1]["level"], "error");
+    assert_eq!(results[1]["ruleIndex"], 1);
+    let location = &results[1]["locations"][0]["physicalLocation"];
+    assert!(location["artifactLocation"]["uri"]
+        .as_str()
+        .unwrap()
+        .ends_with("/resources/sarif/nested.sinc"));
+    assert_eq!(location["region"]["startLine"], 2);
+    assert_eq!(location["region"]["startColumn"], 5);
+    let related: Vec<_> = results[1]["relatedLocations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| {
+            (
+                location["message"]["text"].as_str().unwrap().to_string(),
+                location["physicalLocation"]["region"]["startLine"].clone(),
+            )
+        })
+        .collect();
+    assert!(related[0].0.ends_with("nested.sinc included here"));
+    assert_eq!(related[0].1, 2);
+    assert!(related[1].0.ends_with("outer.sinc included here"));
+    assert_eq!(related[1].1, 1);
+    assert!(results[2].get("locations").is_none());
+}
+
+#[test]
+fn rules_of_error_kinds() {
+    let mut log = SarifLog::new();
+    for source in &["@if 1 / 0\n@endif\n", "@if \"a\" < 1\n@endif\n"] {
+        let error = SleighPreprocessor::new(HashMap::new(), "x.slaspec", true)
+            .with_source(*source)
+            .process(&mut String::new())
+            .unwrap_err();
+        log.add_error(&error);
+    }
+    let log: Value = serde_json::from_str(&log.to_json()).unwrap();
+    let rule_ids: Vec<_> = log["runs"][0]["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["ruleId"].as_str().unwrap())
+        .collect();
+    assert_eq!(rule_ids, ["arithmetic", "type"]);
+}
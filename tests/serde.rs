@@ -0,0 +1,72 @@
+#![cfg(feature = "serde")]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn preprocessor(source: &str) -> SleighPreprocessor {
+    SleighPreprocessor::new(HashMap::new(), PathBuf::from("dir/x.slaspec"), true)
+        .with_source(source)
+}
+
+#[test]
+fn serialize_results() {
+    let mut preprocessor = preprocessor("@define A \"1\"\n  @pragma\n");
+    preprocessor.process(&mut String::new()).unwrap();
+    assert_eq!(
+        serde_json::to_value(preprocessor.definitions()).unwrap(),
+        json!({"A": "1"})
+    );
+    assert_eq!(
+        serde_json::to_value(preprocessor.locations()).unwrap(),
+        json!([{"filepath": "dir/x.slaspec", "local_line_num": 1, "global_line_num": 1}])
+    );
+    assert_eq!(
+        serde_json::to_value(preprocessor.diagnostics()).unwrap(),
+        json!([{
+            "severity": "warning",
+            "kind": "unrecognized_directive",
+            "message": "unrecognized indented preprocessor directive copied as text",
+            "path": "dir/x.slaspec",
+            "line_no": 2,
+            "overall_line_no": 2,
+            "line": "  @pragma",
+        }])
+    );
+}
+
+#[test]
+fn serialize_errors() {
+    let error = preprocessor("$(B)\n")
+        .process(&mut String::new())
+        .unwrap_err();
+    assert_eq!(
+        serde_json::to_value(&error).unwrap(),
+        json!({
+            "kind": "not_defined",
+            "message": "Preprocessor error: unknown variable: B at x.slaspec:1(1): $(B)",
+            "preprocessor": {
+                "kind": "not_defined",
+                "message": "unknown variable: B",
+                "path": "x.slaspec",
+                "line_no": 1,
+                "overall_line_no": 1,
+                "line": "$(B)",
+                "file_path": "dir/x.slaspec",
+                "column": 1,
+            },
+        })
+    );
+    let error = preprocessor("@if 1 +\n@endif\n")
+        .process(&mut String::new())
+        .unwrap_err();
+    assert_eq!(serde_json::to_value(&error).unwrap()["kind"], "parsing");
+    let error = sleigh_preprocessor::errors::Error::NotDefined("C".into());
+    assert_eq!(
+        serde_json::to_value(&error).unwrap(),
+        json!({"kind": "not_defined", "message": "Identifier \"C\" not defined", "preprocessor": null})
+    );
+}
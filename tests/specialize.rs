@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use sleigh_preprocessor::boolean_expression::KnownDefinitions;
+use sleigh_preprocessor::specialize::{Specializer, UnresolvedInclude};
+use sleigh_preprocessor::SleighPreprocessor;
+
+fn specialize(input: &str, known: KnownDefinitions) -> String {
+    Specializer::new(known)
+        .specialize(input, "test.slaspec")
+        .unwrap()
+}
+
+#[test]
+fn specialize_decided_conditionals() {
+    let input = "\
+@ifdef A
+a
+@else
+not a
+@endif
+@ifndef A
+@endif
+text # with a comment
+";
+    let mut known = KnownDefinitions::new();
+    known.define("A", "");
+    assert_eq!(specialize(input, known), "a\ntext # with a comment\n");
+    let mut known = KnownDefinitions::new();
+    known.undefine("A");
+    assert_eq!(specialize(input, known), "not a\ntext # with a comment\n");
+}
+
+#[test]
+fn specialize_undecided_conditionals() {
+    let input = "\
+@if defined(A) && B == \"1\"   # keep the comment
+b
+@elif defined(C)
+c
+@elif defined(A)
+a
+@else
+none
+@endif
+";
+    let mut known = KnownDefinitions::new();
+    assert_eq!(specialize(input, known.clone()), input);
+    known.define("A", "");
+    known.undefine("C");
+    assert_eq!(
+        specialize(input, known),
+        "@if B == \"1\" # keep the comment\nb\n@else\na\n@endif\n"
+    );
+    let mut known = KnownDefinitions::new();
+    known.undefine("A");
+    assert_eq!(
+        specialize(input, known),
+        "@if defined(C)\nc\n@else\nnone\n@endif\n"
+    );
+}
+
+#[test]
+fn specialize_follows_definitions() {
+    let input = "\
+@define X \"1\"
+@ifdef Y
+@undef X
+@endif
+@if X == \"1\"
+x
+@endif
+@ifdef X
+defined
+@endif
+";
+    assert_eq!(
+        specialize(input, KnownDefinitions::new()),
+        "\
+@define X \"1\"
+@ifdef Y
+@undef X
+@endif
+@if X == \"1\"
+x
+@endif
+@ifdef X
+defined
+@endif
+"
+    );
+    let mut known = KnownDefinitions::new();
+    known.undefine("Y");
+    assert_eq!(specialize(input, known), "@define X \"1\"\nx\ndefined\n");
+}
+
+#[test]
+fn specialize_inline_expansions() {
+    let input = "define $(SIZE) $(OTHER)\r\n@include \"$(DIR)/file.sinc\"\r\n";
+    let mut known = KnownDefinitions::new();
+    known.define("SIZE", "4");
+    known.define("DIR", "arm");
+    let specializer = Specializer::new(known);
+    assert_eq!(specializer.specialize(input, "test").unwrap(), input);
+    assert_eq!(
+        specializer
+            .with_inline_expansions(true)
+            .specialize(input, "test")
+            .unwrap(),
+        "define 4 $(OTHER)\r\n@include \"arm/file.sinc\"\r\n"
+    );
+}
+
+#[test]
+fn specialize_errors() {
+    let specializer = Specializer::new(KnownDefinitions::new());
+    let error = specializer.specialize("@ifdef A\n@else\n@elif B\n@endif\n", "test.slaspec");
+    assert_eq!(
+        error.unwrap_err().to_string(),
+        "Preprocessor error: already saw else directive at test.slaspec:3(3): @elif B"
+    );
+    assert!(specializer
+        .specialize("@ifdef A\n", "test.slaspec")
+        .is_err());
+    assert!(specializer
+        .specialize("@if (\n@endif\n", "test.slaspec")
+        .is_err());
+    // conditions of dropped branches are not parsed
+    let mut known = KnownDefinitions::new();
+    known.undefine("A");
+    assert_eq!(specialize("@ifdef A\n@if (\n@endif\n@endif\n", known), "");
+}
+
+#[test]
+fn specialize_tree_errors() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/specialize/error");
+    let error = Specializer::new(KnownDefinitions::new())
+        .specialize_tree(root.join("root.slaspec"))
+        .unwrap_err();
+    // numbered as the preprocessor does
+    let preprocessed = SleighPreprocessor::new(HashMap::new(), root.join("root.slaspec"), true)
+        .process(&mut String::new())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Preprocessor error: not in IF* directive at bad.sinc:2(4): @endif"
+    );
+    assert_eq!(error.to_string(), preprocessed.to_string());
+}
+
+#[test]
+fn specialize_tree() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/specialize");
+    let mut known = KnownDefinitions::new();
+    known.define("VARIANT", "arm");
+    known.define("THUMB", "");
+    let tree = Specializer::new(known)
+        .specialize_tree(root.join("root.slaspec"))
+        .unwrap();
+    let files: Vec<_> = tree
+        .files()
+        .iter()
+        .map(|(path, content)| (path.strip_prefix(&root).unwrap(), content.as_str()))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (
+                PathBuf::from("root.slaspec").as_path(),
+                "\
+# Root of the specialization test
+@define ENDIAN \"big\"
+@include \"inc/common.sinc\"
+define endian=big;
+@include \"inc/$(VARIANT).sinc\"
+@include \"$(FAMILY)/extra.sinc\"
+"
+            ),
+            (
+                PathBuf::from("inc/common.sinc").as_path(),
+                "\
+@if ARCH == \"v7\"
+define token thumb (16) op=(0,15);
+@else
+define token thumb (16) op=(8,15);
+@endif
+@define COMMON_LOADED
+"
+            ),
+            (PathBuf::from("inc/arm.sinc").as_path(), ":nop is op=0 {}\n"),
+        ]
+    );
+    assert_eq!(
+        tree.unresolved_includes(),
+        &[UnresolvedInclude {
+            path: root.join("root.slaspec"),
+            line_no: 12,
+            include: "$(FAMILY)/extra.sinc".to_string(),
+        }]
+    );
+}
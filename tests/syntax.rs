@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::PathBuf;
+
+use sleigh_preprocessor::syntax::{DirectiveKind, Node, SyntaxTree};
+
+#[test]
+fn round_trip_resources() {
+    let resources = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources");
+    for entry in fs::read_dir(resources).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() {
+            let source = fs::read_to_string(&path).unwrap();
+            assert_eq!(SyntaxTree::parse(&source).to_string(), source, "{:?}", path);
+        }
+    }
+}
+
+#[test]
+fn round_trip_unusual_sources() {
+    for source in &[
+        "",
+        "no newline",
+        "@endif\n@else\n",
+        "@if A\r\n@ifdef B\r\ntext",
+        "@unknown directive # comment\n\n\n",
+    ] {
+        assert_eq!(SyntaxTree::parse(source).to_string(), *source);
+    }
+}
+
+#[test]
+fn conditional_structure() {
+    let source = "\
+text
+# comment
+@ifdef A
+a
+@elif B == \"1\"   # comment
+@if C
+c
+@endif
+@else
+@define X 1
+@endif
+@endif
+";
+    let tree = SyntaxTree::parse(source);
+    let nodes = tree.nodes();
+    assert_eq!(nodes.len(), 3);
+    assert_eq!(nodes[0], Node::Text(0..15));
+    assert_eq!(nodes[1].span(), 15..source.len() - "@endif\n".len());
+    let conditional = match &nodes[1] {
+        Node::Conditional(conditional) => conditional,
+        node => panic!("{:?}", node),
+    };
+    let kinds: Vec<_> = conditional
+        .branches
+        .iter()
+        .map(|branch| branch.directive.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            DirectiveKind::Ifdef,
+            DirectiveKind::Elif,
+            DirectiveKind::Else
+        ]
+    );
+    let elif = &conditional.branches[1].directive;
+    assert_eq!(elif.line_no, 5);
+    assert_eq!(tree.text(&elif.name), "elif");
+    assert_eq!(tree.text(&elif.arguments), "B == \"1\"");
+    assert_eq!(tree.text(elif.comment.as_ref().unwrap()), "# comment");
+    assert!(matches!(
+        conditional.branches[1].body[..],
+        [Node::Conditional(_)]
+    ));
+    match &conditional.branches[2].body[..] {
+        [Node::Directive(define)] => {
+            assert_eq!(define.kind, DirectiveKind::Define);
+            assert_eq!(tree.text(&define.arguments), "X 1");
+            assert_eq!(define.comment, None);
+        }
+        body => panic!("{:?}", body),
+    }
+    assert_eq!(conditional.endif.as_ref().unwrap().line_no, 11);
+    // the last @endif has no @if
+    match &nodes[2] {
+        Node::Directive(endif) => assert_eq!(endif.kind, DirectiveKind::Endif),
+        node => panic!("{:?}", node),
+    }
+}
+
+#[test]
+fn unterminated_conditional() {
+    let source = "@if A\n@ifdef B\nb\n";
+    let tree = SyntaxTree::parse(source);
+    match tree.nodes() {
+        [outer @ Node::Conditional(conditional)] => {
+            assert_eq!(conditional.endif, None);
+            assert_eq!(outer.span(), 0..source.len());
+        }
+        nodes => panic!("{:?}", nodes),
+    }
+}
+
+#[test]
+fn comments_outside_quotes() {
+    let source = "\
+@define A \"x#y\\\"#\" # comment
+@if A =~ /#\\/#/ && B == \"#\"#comment
+@endif
+@include \"unterminated # comment
+";
+    let tree = SyntaxTree::parse(source);
+    let directives: Vec<_> = tree
+        .nodes()
+        .iter()
+        .flat_map(|node| match node {
+            Node::Directive(directive) => vec![directive.clone()],
+            Node::Conditional(conditional) => vec![conditional.branches[0].directive.clone()],
+            Node::Text(_) => vec![],
+        })
+        .map(|d| {
+            (
+                tree.text(&d.arguments),
+                d.comment.map(|comment| tree.text(&comment)),
+            )
+        })
+        .collect();
+    assert_eq!(
+        directives,
+        vec![
+            ("A \"x#y\\\"#\"", Some("# comment")),
+            ("A =~ /#\\/#/ && B == \"#\"", Some("#comment")),
+            ("\"unterminated", Some("# comment")),
+        ]
+    );
+}